@@ -57,7 +57,7 @@ pub fn poll_rd1(fd: int, waittime: int) -> int
 }
 
 
-fn on_key(tk: &mut termkey::TermKey, key: termkey::TermKeyEvent)
+fn on_key(tk: &mut termkey::TermKey, key: &termkey::TermKeyEvent)
 {
     let s = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_VIM);
     println!("{}", s);
@@ -76,7 +76,7 @@ fn main()
         {
             match tk.getkey_force()
             {
-                termkey::Key(key) => { on_key(&mut tk, key) }
+                termkey::Key(key) => { on_key(&mut tk, &key) }
                 _ => {}
             }
         }
@@ -90,7 +90,7 @@ fn main()
             {
                 termkey::Key(key) =>
                 {
-                    on_key(&mut tk, key);
+                    on_key(&mut tk, &key);
                     match key
                     {
                         termkey::UnicodeEvent{mods, codepoint, utf8: _} =>
@@ -13,7 +13,15 @@ fn main()
     let mouse_proto = 0i; // TODO parse arg -p (no default)
     let format = termkey::c::TERMKEY_FORMAT_VIM;
 
-    let mut tk = termkey::TermKey::new(0, termkey::c::TERMKEY_FLAG_SPACESYMBOL|termkey::c::TERMKEY_FLAG_CTRLC);
+    let mut tk = match termkey::TermKey::new_controlling_terminal(termkey::c::TERMKEY_FLAG_SPACESYMBOL|termkey::c::TERMKEY_FLAG_CTRLC)
+    {
+        Some(tk) => tk,
+        None =>
+        {
+            println!("No controlling terminal");
+            return;
+        }
+    };
     if !(tk.get_flags() & termkey::c::TERMKEY_FLAG_UTF8).is_empty()
     {
         println!("Termkey in UTF-8 mode")
@@ -37,7 +45,7 @@ fn main()
             termkey::Eof => break,
             termkey::Key(key) =>
             {
-                let s = tk.strfkey(key, format);
+                let s = tk.strfkey(&key, format);
                 println!("Key {}", s);
 
                 match key
@@ -50,10 +58,10 @@ fn main()
                     {
                         println!("Cursor position report at line={}, col={}\n", line, col)
                     }
-                    termkey::ModeReportEvent{initial, mode, value} =>
+                    termkey::ModeReportEvent{mode, state} =>
                     {
-                        let initial_str = if initial != 0 { "DEC" } else { "ANSI" };
-                        println!("Mode report {} mode {} = {}\n", initial_str, mode, value)
+                        let initial_str = if mode.is_dec() { "DEC" } else { "ANSI" };
+                        println!("Mode report {} mode {} = {}\n", initial_str, mode.number(), state)
                     }
                     termkey::UnknownCsiEvent =>
                     {
@@ -71,8 +79,12 @@ fn main()
                         }
                         if mods.is_empty() && codepoint == '?'
                         {
-                            // println!("\x1b[?6n"); // DECDSR 6 == request cursor position
-                            println!("\x1b[?1$p"); // DECRQM == request mode, DEC origin mode
+                            // DECRQM == request mode, DEC cursor keys mode
+                            match tk.query_mode(termkey::mode::CursorKeys, 1000)
+                            {
+                                Some(state) => println!("Mode report DEC mode 1 = {}\n", state),
+                                None => println!("No reply to mode request\n"),
+                            }
                         }
                     }
                     _ => {}
@@ -0,0 +1,39 @@
+extern crate native;
+
+extern crate termkey;
+
+#[start]
+fn start(argc: int, argv: *const *const u8) -> int {
+    native::start(argc, argv, main)
+}
+
+// fuzz TARGET < input
+//
+// Runs one of the targets in termkey::fuzz on stdin, for afl-fuzz and the
+// like; a crash is a bug.
+fn main()
+{
+    let args = std::os::args();
+    let name = if args.len() == 2 { args[1].as_slice() } else { "" };
+    let target = termkey::fuzz::TARGETS.iter().find(|&&(n, _)| n == name);
+    let target = match target
+    {
+        Some(&(_, target)) => target,
+        None =>
+        {
+            let names: Vec<&str> = termkey::fuzz::TARGETS.iter().map(|&(n, _)| n).collect();
+            println!("usage: fuzz {{{}}} < input", names.connect("|"));
+            std::os::set_exit_status(2);
+            return;
+        }
+    };
+    match std::io::stdin().read_to_end()
+    {
+        Ok(data) => target(data.as_slice()),
+        Err(e) =>
+        {
+            println!("{}", e);
+            std::os::set_exit_status(1);
+        }
+    }
+}
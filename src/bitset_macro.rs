@@ -10,7 +10,7 @@ macro_rules! bitset(
         $($VALUE:ident = $value:expr),+
     }) => (
         #[repr(C)]
-        #[deriving(PartialEq, PartialOrd)]
+        #[deriving(PartialEq, PartialOrd, Clone)]
         pub struct $BitSet {
             bits: $T,
         }
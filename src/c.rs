@@ -16,7 +16,7 @@ pub unsafe fn TERMKEY_CHECK_VERSION()
     termkey_check_version(TERMKEY_VERSION_MAJOR, TERMKEY_VERSION_MINOR);
 }
 
-#[repr(C)] #[deriving(PartialEq, PartialOrd)]
+#[repr(C)] #[deriving(PartialEq, PartialOrd, Clone)]
 pub enum TermKeySym
 {
   TERMKEY_SYM_UNKNOWN = -1,
@@ -129,7 +129,7 @@ pub enum TermKeyResult
   TERMKEY_RES_ERROR
 }
 
-#[repr(C)] #[deriving(PartialEq, PartialOrd)]
+#[repr(C)] #[deriving(PartialEq, PartialOrd, Clone)]
 pub enum TermKeyMouseEvent
 {
   TERMKEY_MOUSE_UNKNOWN,
@@ -0,0 +1,95 @@
+// Charsets for TERMKEY_FLAG_RAW input. Without one, bytes from 0x80 up
+// are ByteEvents, as they came; with one, each is read as the character
+// it stands for, where there is one.
+//
+// All of these agree with ASCII below 0x80, which libtermkey decodes
+// itself.
+
+use TermKey;
+
+pub trait Charset
+{
+    // None leaves byte as a ByteEvent.
+    fn decode(&self, byte: u8) -> Option<char>;
+}
+
+// ISO 8859-1; 0x80 to 0x9f are the C1 controls, so left as bytes.
+pub struct Latin1;
+
+// The IBM PC's, as serial consoles and DOS programs use it.
+pub struct Cp437;
+
+pub struct Koi8R;
+
+impl Charset for Latin1
+{
+    fn decode(&self, byte: u8) -> Option<char>
+    {
+        if byte >= 0xa0 { Some(byte as char) } else { None }
+    }
+}
+
+impl Charset for Cp437
+{
+    fn decode(&self, byte: u8) -> Option<char>
+    {
+        if byte >= 0x80 { Some(CP437[(byte - 0x80) as uint]) } else { None }
+    }
+}
+
+impl Charset for Koi8R
+{
+    fn decode(&self, byte: u8) -> Option<char>
+    {
+        if byte >= 0x80 { Some(KOI8_R[(byte - 0x80) as uint]) } else { None }
+    }
+}
+
+impl TermKey
+{
+    // None for ByteEvents again.
+    pub fn set_charset(&mut self, charset: Option<Box<Charset + 'static>>)
+    {
+        self.charset = charset;
+    }
+}
+
+// 0x80 to 0xff
+static CP437: [char, ..128] = [
+    '\u00c7', '\u00fc', '\u00e9', '\u00e2', '\u00e4', '\u00e0', '\u00e5', '\u00e7',
+    '\u00ea', '\u00eb', '\u00e8', '\u00ef', '\u00ee', '\u00ec', '\u00c4', '\u00c5',
+    '\u00c9', '\u00e6', '\u00c6', '\u00f4', '\u00f6', '\u00f2', '\u00fb', '\u00f9',
+    '\u00ff', '\u00d6', '\u00dc', '\u00a2', '\u00a3', '\u00a5', '\u20a7', '\u0192',
+    '\u00e1', '\u00ed', '\u00f3', '\u00fa', '\u00f1', '\u00d1', '\u00aa', '\u00ba',
+    '\u00bf', '\u2310', '\u00ac', '\u00bd', '\u00bc', '\u00a1', '\u00ab', '\u00bb',
+    '\u2591', '\u2592', '\u2593', '\u2502', '\u2524', '\u2561', '\u2562', '\u2556',
+    '\u2555', '\u2563', '\u2551', '\u2557', '\u255d', '\u255c', '\u255b', '\u2510',
+    '\u2514', '\u2534', '\u252c', '\u251c', '\u2500', '\u253c', '\u255e', '\u255f',
+    '\u255a', '\u2554', '\u2569', '\u2566', '\u2560', '\u2550', '\u256c', '\u2567',
+    '\u2568', '\u2564', '\u2565', '\u2559', '\u2558', '\u2552', '\u2553', '\u256b',
+    '\u256a', '\u2518', '\u250c', '\u2588', '\u2584', '\u258c', '\u2590', '\u2580',
+    '\u03b1', '\u00df', '\u0393', '\u03c0', '\u03a3', '\u03c3', '\u00b5', '\u03c4',
+    '\u03a6', '\u0398', '\u03a9', '\u03b4', '\u221e', '\u03c6', '\u03b5', '\u2229',
+    '\u2261', '\u00b1', '\u2265', '\u2264', '\u2320', '\u2321', '\u00f7', '\u2248',
+    '\u00b0', '\u2219', '\u00b7', '\u221a', '\u207f', '\u00b2', '\u25a0', '\u00a0',
+];
+
+// 0x80 to 0xff
+static KOI8_R: [char, ..128] = [
+    '\u2500', '\u2502', '\u250c', '\u2510', '\u2514', '\u2518', '\u251c', '\u2524',
+    '\u252c', '\u2534', '\u253c', '\u2580', '\u2584', '\u2588', '\u258c', '\u2590',
+    '\u2591', '\u2592', '\u2593', '\u2320', '\u25a0', '\u2219', '\u221a', '\u2248',
+    '\u2264', '\u2265', '\u00a0', '\u2321', '\u00b0', '\u00b2', '\u00b7', '\u00f7',
+    '\u2550', '\u2551', '\u2552', '\u0451', '\u2553', '\u2554', '\u2555', '\u2556',
+    '\u2557', '\u2558', '\u2559', '\u255a', '\u255b', '\u255c', '\u255d', '\u255e',
+    '\u255f', '\u2560', '\u2561', '\u0401', '\u2562', '\u2563', '\u2564', '\u2565',
+    '\u2566', '\u2567', '\u2568', '\u2569', '\u256a', '\u256b', '\u256c', '\u00a9',
+    '\u044e', '\u0430', '\u0431', '\u0446', '\u0434', '\u0435', '\u0444', '\u0433',
+    '\u0445', '\u0438', '\u0439', '\u043a', '\u043b', '\u043c', '\u043d', '\u043e',
+    '\u043f', '\u044f', '\u0440', '\u0441', '\u0442', '\u0443', '\u0436', '\u0432',
+    '\u044c', '\u044b', '\u0437', '\u0448', '\u044d', '\u0449', '\u0447', '\u044a',
+    '\u042e', '\u0410', '\u0411', '\u0426', '\u0414', '\u0415', '\u0424', '\u0413',
+    '\u0425', '\u0418', '\u0419', '\u041a', '\u041b', '\u041c', '\u041d', '\u041e',
+    '\u041f', '\u042f', '\u0420', '\u0421', '\u0422', '\u0423', '\u0416', '\u0412',
+    '\u042c', '\u042b', '\u0417', '\u0428', '\u042d', '\u0429', '\u0427', '\u042a',
+];
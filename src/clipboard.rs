@@ -0,0 +1,60 @@
+// OSC 52 clipboard reads, for when the terminal's clipboard is the only
+// one around (e.g. over SSH). The terminal has to allow it, and many
+// don't by default, so expect timeouts.
+
+use serialize::base64::FromBase64;
+
+use {TermKey, OscEvent};
+
+impl TermKey
+{
+    // selection is any of the OSC 52 selection characters, e.g. "c" for
+    // the clipboard or "p" for the primary selection; empty means "s 0".
+    // Anything else is refused, as it would go into the request as-is.
+    // Only a reply for the same selection is taken as the answer.
+    //
+    // Replies longer than get_string_limit() are dropped, so raise that
+    // first if large contents are expected.
+    pub fn request_clipboard(&mut self, selection: &str, timeout_ms: int) -> Option<Vec<u8>>
+    {
+        if !selection.chars().all(|c| "cpqs01234567".contains_char(c))
+        {
+            return None;
+        }
+        self.write_request(format!("\x1b]52;{};?\x1b\\", selection).as_bytes());
+        self.wait_for_reply(timeout_ms, |key|
+        {
+            match *key
+            {
+                OscEvent{number: 52, ref payload} => decode_reply(payload.as_slice(), selection),
+                _ => None,
+            }
+        })
+    }
+}
+
+// selection ; base64, if the selection is the one asked for
+fn decode_reply(payload: &[u8], selection: &str) -> Option<Vec<u8>>
+{
+    let (pc, data) = match payload.iter().position(|&b| b == b';')
+    {
+        Some(i) => (payload.slice_to(i), payload.slice_from(i + 1)),
+        None => return None,
+    };
+    // xterm answers a request for "" as "s0"
+    let same = pc == selection.as_bytes() || (selection.is_empty() && pc == "s0".as_bytes());
+    if !same
+    {
+        return None;
+    }
+    // a terminal that refuses may echo the '?' back
+    if data == "?".as_bytes()
+    {
+        return None;
+    }
+    match data.from_base64()
+    {
+        Ok(bytes) => Some(bytes),
+        Err(_) => None,
+    }
+}
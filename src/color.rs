@@ -0,0 +1,135 @@
+// Terminal colour queries: OSC 10 (foreground), OSC 11 (background)
+// and OSC 4 (palette), answered with X11-style rgb: specifications.
+
+use {TermKey, OscEvent};
+
+// 16 bits per channel, as in the replies
+#[deriving(PartialEq, PartialOrd, Clone, Show)]
+pub struct Rgb
+{
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Rgb
+{
+    // "rgb:R/G/B", where each channel is 1 to 4 hex digits
+    pub fn parse(s: &str) -> Option<Rgb>
+    {
+        if !s.starts_with("rgb:")
+        {
+            return None;
+        }
+        let channels: Vec<&str> = s.slice_from(4).split('/').collect();
+        if channels.len() != 3
+        {
+            return None;
+        }
+        let r = parse_channel(channels[0]);
+        let g = parse_channel(channels[1]);
+        let b = parse_channel(channels[2]);
+        match (r, g, b)
+        {
+            (Some(r), Some(g), Some(b)) => Some(Rgb{r: r, g: g, b: b}),
+            _ => None,
+        }
+    }
+
+    // as from 8-bit channels
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Rgb
+    {
+        Rgb{r: r as u16 * 0x101, g: g as u16 * 0x101, b: b as u16 * 0x101}
+    }
+
+    // relative luminance, as in WCAG: the channels taken as sRGB and
+    // made linear, then weighted as in ITU-R BT.709; 0.0 to 1.0
+    pub fn luminance(&self) -> f64
+    {
+        0.2126 * linear(self.r) + 0.7152 * linear(self.g) + 0.0722 * linear(self.b)
+    }
+
+    // darker than about halfway to white, to the eye (L* 50)
+    pub fn is_dark(&self) -> bool
+    {
+        self.luminance() < 0.18
+    }
+}
+
+// undo the sRGB transfer function
+fn linear(channel: u16) -> f64
+{
+    let v = channel as f64 / 65535.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+// scale h/hh/hhh/hhhh to 16 bits
+fn parse_channel(s: &str) -> Option<u16>
+{
+    if s.len() < 1 || s.len() > 4
+    {
+        return None;
+    }
+    let v: u16 = match ::std::num::from_str_radix(s, 16)
+    {
+        Some(v) => v,
+        None => return None,
+    };
+    let max = (1u32 << (4 * s.len())) - 1;
+    Some((v as u32 * 0xffff / max) as u16)
+}
+
+// the colour part of a reply; terminals don't use names here
+fn reply_color(payload: &[u8]) -> Option<Rgb>
+{
+    match ::std::str::from_utf8(payload)
+    {
+        Some(s) => Rgb::parse(s),
+        None => None,
+    }
+}
+
+// Any keys that arrive while waiting for the reply are queued,
+// as for query_cursor_position.
+impl TermKey
+{
+    pub fn query_foreground(&mut self, timeout_ms: int) -> Option<Rgb>
+    {
+        self.query_dynamic_color(10, timeout_ms)
+    }
+
+    pub fn query_background(&mut self, timeout_ms: int) -> Option<Rgb>
+    {
+        self.query_dynamic_color(11, timeout_ms)
+    }
+
+    pub fn query_palette(&mut self, index: u8, timeout_ms: int) -> Option<Rgb>
+    {
+        self.write_request(format!("\x1b]4;{};?\x1b\\", index).as_bytes());
+        let prefix = format!("{};", index);
+        self.wait_for_reply(timeout_ms, |key|
+        {
+            match *key
+            {
+                OscEvent{number: 4, ref payload} if payload.as_slice().starts_with(prefix.as_bytes()) =>
+                {
+                    reply_color(payload.slice_from(prefix.len()))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn query_dynamic_color(&mut self, number: int, timeout_ms: int) -> Option<Rgb>
+    {
+        self.write_request(format!("\x1b]{};?\x1b\\", number).as_bytes());
+        self.wait_for_reply(timeout_ms, |key|
+        {
+            match *key
+            {
+                OscEvent{number: n, ref payload} if n == number => reply_color(payload.as_slice()),
+                _ => None,
+            }
+        })
+    }
+}
@@ -0,0 +1,119 @@
+// Device Attributes (DA1/DA2/DA3) and XTVERSION replies, and a probe
+// that asks for all of them.
+
+use std::collections::Deque;
+
+use c;
+use {TermKey, DeviceAttributesEvent, TerminalVersionEvent};
+
+#[deriving(PartialEq, PartialOrd, Clone)]
+pub enum DeviceAttributes
+{
+    // DA1: CSI ? class ; features... c
+    // class is 1 for a VT100, 6 for a VT102, 62 and up for a VT220 and up
+    Primary{pub class: int, pub features: Vec<int>},
+    // DA2: CSI > terminal ; firmware ; rom c
+    Secondary{pub terminal: int, pub firmware: int, pub rom: int},
+    // DA3: DCS ! | unit-id ST
+    Tertiary{pub unit_id: String},
+}
+
+impl DeviceAttributes
+{
+    // cmd and args as from termkey_interpret_csi
+    pub fn from_csi(cmd: c::c_ulong, args: &[c::c_long]) -> Option<DeviceAttributes>
+    {
+        if cmd == ('?' as c::c_ulong) << 8 | 'c' as c::c_ulong
+        {
+            Some(Primary{class: arg(args, 0), features: range(1, args.len()).map(|i| arg(args, i)).collect()})
+        }
+        else if cmd == ('>' as c::c_ulong) << 8 | 'c' as c::c_ulong
+        {
+            Some(Secondary{terminal: arg(args, 0), firmware: arg(args, 1), rom: arg(args, 2)})
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+// termkey_interpret_csi uses -1 for a missing argument
+fn arg(args: &[c::c_long], i: uint) -> int
+{
+    if i < args.len() && args[i] >= 0 { args[i] as int } else { 0 }
+}
+
+impl ::std::fmt::Show for DeviceAttributes
+{
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        match *self
+        {
+            Primary{class, ref features} =>
+            {
+                try!(write!(fmt, "DA1({}", class));
+                for f in features.iter()
+                {
+                    try!(write!(fmt, ";{}", f));
+                }
+                write!(fmt, ")")
+            }
+            Secondary{terminal, firmware, rom} =>
+            {
+                write!(fmt, "DA2({};{};{})", terminal, firmware, rom)
+            }
+            Tertiary{ref unit_id} =>
+            {
+                write!(fmt, "DA3({})", unit_id)
+            }
+        }
+    }
+}
+
+pub struct TerminalInfo
+{
+    pub primary: Option<DeviceAttributes>,
+    pub secondary: Option<DeviceAttributes>,
+    pub tertiary: Option<DeviceAttributes>,
+    // e.g. "xterm(388)"
+    pub version: Option<String>,
+}
+
+impl TermKey
+{
+    // Sends XTVERSION, DA3, DA2 and DA1 queries, and gathers the replies.
+    //
+    // Every terminal answers DA1, and they answer in order, so the probe
+    // is over as soon as DA1 comes back, or at the timeout for things
+    // that aren't terminals at all.
+    pub fn probe_terminal(&mut self, timeout_ms: int) -> TerminalInfo
+    {
+        let mut info = TerminalInfo{primary: None, secondary: None, tertiary: None, version: None};
+
+        self.write_request("\x1b[>0q\x1b[=c\x1b[>c\x1b[c".as_bytes());
+        let deadline = ::deadline(timeout_ms);
+        loop
+        {
+            match self.waitkey_until(deadline)
+            {
+                Some(DeviceAttributesEvent{attrs}) =>
+                {
+                    match attrs
+                    {
+                        Primary{..} => { info.primary = Some(attrs); break; }
+                        Secondary{..} => { info.secondary = Some(attrs); }
+                        Tertiary{..} => { info.tertiary = Some(attrs); }
+                    }
+                }
+                Some(TerminalVersionEvent{version}) =>
+                {
+                    info.version = Some(version);
+                }
+                Some(key) => { self.queue.push_back(key); }
+                None => break,
+            }
+        }
+        info
+    }
+}
@@ -0,0 +1,143 @@
+// Fuzz targets: each takes arbitrary bytes, and must return without
+// failing the task whatever they are. examples/fuzz.rs runs one of them
+// on stdin (for afl-fuzz and the like), and test_59fuzz runs them all on
+// generated and known-nasty input.
+
+use c;
+use inputrc;
+use kbd;
+use tmux;
+use vscode;
+use {TermKey, Key, Again};
+
+// (name, target), for drivers to choose from
+pub static TARGETS: &'static [(&'static str, fn(&[u8]))] = &[
+    ("decode", decode),
+    ("strpkey", strpkey),
+    ("keymaps", keymaps),
+];
+
+static FORMAT_FLAGS: &'static [c::TermKeyFormat] = &[
+    c::TERMKEY_FORMAT_LONGMOD,
+    c::TERMKEY_FORMAT_CARETCTRL,
+    c::TERMKEY_FORMAT_ALTISMETA,
+    c::TERMKEY_FORMAT_WRAPBRACKET,
+    c::TERMKEY_FORMAT_SPACEMOD,
+    c::TERMKEY_FORMAT_LOWERMOD,
+    c::TERMKEY_FORMAT_LOWERSPACE,
+    c::TERMKEY_FORMAT_MOUSE_POS,
+];
+
+// Every combination of the format flags; VIM and URWID are among them.
+pub fn all_formats() -> Vec<c::TermKeyFormat>
+{
+    range(0u, 1 << FORMAT_FLAGS.len()).map(|bits|
+    {
+        let mut format = c::TermKeyFormat::empty();
+        for (i, &flag) in FORMAT_FLAGS.iter().enumerate()
+        {
+            if bits & 1 << i != 0
+            {
+                format.insert(flag);
+            }
+        }
+        format
+    }).collect()
+}
+
+// The first byte picks the flags, the second where to split the rest
+// into two pushes; the rest is input, decoded and then formatted.
+pub fn decode(data: &[u8])
+{
+    if data.len() < 2
+    {
+        return;
+    }
+    let mut flags = if data[0] & 1 != 0 { c::TERMKEY_FLAG_RAW } else { c::TERMKEY_FLAG_UTF8 };
+    if data[0] & 2 != 0 { flags.insert(c::TERMKEY_FLAG_SPACESYMBOL); }
+    if data[0] & 4 != 0 { flags.insert(c::TERMKEY_FLAG_CONVERTKP); }
+    if data[0] & 8 != 0 { flags.insert(c::TERMKEY_FLAG_NOINTERPRET); }
+    let term = if data[0] & 16 != 0 { "vt100" } else { "xterm" };
+    let input = data.slice_from(2);
+    let split = ::std::cmp::min(data[1] as uint, input.len());
+
+    let mut tk = TermKey::new_abstract(term, flags);
+    tk.set_8bit_controls(data[0] & 32 != 0);
+    tk.set_high_bit_alt(data[0] & 64 != 0);
+    let formats = all_formats();
+    let mut n = 0u;
+    for part in [input.slice_to(split), input.slice_from(split)].iter()
+    {
+        let mut rest = *part;
+        loop
+        {
+            let len = tk.push_bytes(rest);
+            rest = rest.slice_from(len);
+            let key = match tk.getkey()
+            {
+                Key(key) => key,
+                // there may be more to come
+                Again if len > 0 => continue,
+                Again => match tk.getkey_force()
+                {
+                    Key(key) => key,
+                    _ if rest.is_empty() => break,
+                    _ => continue,
+                },
+                _ if rest.is_empty() => break,
+                _ => continue,
+            };
+            tk.strfkey(&key, formats[n % formats.len()]);
+            n += 1;
+        }
+    }
+}
+
+// The input as a string, parsed in every format; whatever parses is
+// formatted again.
+pub fn strpkey(data: &[u8])
+{
+    let s = String::from_utf8_lossy(data).into_string();
+    let mut tk = TermKey::new_abstract("xterm", c::TERMKEY_FLAG_UTF8);
+    for &format in all_formats().iter()
+    {
+        match tk.strpkey(s.as_slice(), format)
+        {
+            Some((key, _)) => { tk.strfkey(&key, format); }
+            None => {}
+        }
+    }
+    let mut sym = c::TERMKEY_SYM_UNKNOWN;
+    tk.lookup_keyname(s.as_slice(), &mut sym);
+    tk.keyname2sym(s.as_slice());
+}
+
+// The input as a string, for each of the key notation parsers, and
+// whatever parses formatted again.
+pub fn keymaps(data: &[u8])
+{
+    let s = String::from_utf8_lossy(data).into_string();
+    let s = s.as_slice();
+    let mut tk = TermKey::new_abstract("xterm", c::TERMKEY_FLAG_UTF8);
+
+    match kbd::parse(&mut tk, s)
+    {
+        Some(keys) => { kbd::format(&mut tk, keys.as_slice()); }
+        None => {}
+    }
+    match vscode::parse(&mut tk, s)
+    {
+        Some(keys) => { vscode::format(&mut tk, keys.as_slice()); }
+        None => {}
+    }
+    match tmux::parse(&mut tk, s)
+    {
+        Some(key) => { tmux::format(&mut tk, &key); }
+        None => {}
+    }
+
+    inputrc::decode_escapes(s);
+    let mut parser = inputrc::InputrcParser::new("xterm", c::TERMKEY_FLAG_UTF8);
+    parser.parse(s);
+    parser.parse_keyname(s);
+}
@@ -0,0 +1,685 @@
+// Input that libtermkey 0.17 can't decode is picked out here, before the
+// rest is handed on with termkey_push_bytes.
+//
+// So far that is:
+// - control strings, which terminals use for replies: DCS (ESC P ... ST)
+//   and OSC (ESC ] ... ST). Either may also end with BEL, as xterm
+//   allows, and strings longer than the limit are dropped.
+// - key sequences learned at runtime (e.g. from XTGETTCAP), which
+//   libtermkey has no way to add to its own table.
+// - bracketed paste (CSI 200~ ... CSI 201~), which is passed on whole
+//   as a PasteEvent rather than as keys; or in pieces, if it is longer
+//   than the limit.
+// - in UTF-8 mode, bytes that aren't UTF-8, which libtermkey would turn
+//   into U+FFFD; they become an InvalidUtf8Event instead, one for each
+//   maximal part of a character, as the Unicode standard suggests.
+// - otherwise (TERMKEY_FLAG_RAW), bytes from 0x80 up, which become a
+//   ByteEvent, so that they arrive as they were sent.
+// - optionally, 8-bit C1 controls, which are turned into their ESC forms
+//   here, so that the rest of the filter and libtermkey only have to
+//   know those; and high-bit Alt, which is one key, and never the start
+//   of a sequence.
+
+use std::collections::{Deque, RingBuf};
+
+use c;
+use keymap;
+use {TermKeyEvent, KeySymEvent, PasteEvent, InvalidUtf8Event, ByteEvent};
+
+static PASTE_START: &'static [u8] = b"\x1b[200~";
+static PASTE_END: &'static [u8] = b"\x1b[201~";
+static PASTE_END_C1: &'static [u8] = b"\x9b201~";
+
+pub enum Chunk
+{
+    Bytes(Vec<u8>),
+    Event(TermKeyEvent),
+}
+
+#[deriving(PartialEq)]
+enum State
+{
+    Ground,
+    // seq might still become a string introducer or a learned key
+    Pending,
+    // inside a control string
+    InString,
+    // ESC inside a string; ST if followed by '\'
+    InStringEscape,
+    // inside a bracketed paste
+    InPaste,
+}
+
+#[deriving(PartialEq)]
+enum StringKind
+{
+    Dcs,
+    Osc,
+}
+
+pub struct InputFilter
+{
+    state: State,
+    seq: Vec<u8>,
+    // the longest learned key that seq starts with
+    matched: Option<(uint, TermKeyEvent)>,
+    keys: Vec<(Vec<u8>, TermKeyEvent)>,
+    kind: StringKind,
+    string: Vec<u8>,
+    // longest string body that will be kept
+    limit: uint,
+    // the current string is longer than the limit
+    overflow: bool,
+    // check UTF-8 between sequences; if not, high bytes are ByteEvents
+    utf8: bool,
+    // the start of a character, in Ground
+    partial: Vec<u8>,
+    // 0x80 to 0x9f are C1 controls
+    c1: bool,
+    // the high bit is Alt
+    meta8: bool,
+    out: RingBuf<Chunk>,
+}
+
+impl InputFilter
+{
+    pub fn new() -> InputFilter
+    {
+        InputFilter{state: Ground, seq: Vec::new(), matched: None, keys: Vec::new(),
+                kind: Dcs, string: Vec::new(), limit: 65536, overflow: false,
+                utf8: false, partial: Vec::new(), c1: false, meta8: false, out: RingBuf::new()}
+    }
+
+    pub fn set_utf8(&mut self, utf8: bool)
+    {
+        self.utf8 = utf8;
+    }
+
+    pub fn get_c1(&self) -> bool
+    {
+        self.c1
+    }
+    pub fn set_c1(&mut self, c1: bool)
+    {
+        self.c1 = c1;
+    }
+
+    pub fn get_meta8(&self) -> bool
+    {
+        self.meta8
+    }
+    pub fn set_meta8(&mut self, meta8: bool)
+    {
+        self.meta8 = meta8;
+    }
+
+    pub fn get_limit(&self) -> uint
+    {
+        self.limit
+    }
+    pub fn set_limit(&mut self, limit: uint)
+    {
+        self.limit = limit;
+    }
+
+    // Replaces any key already learned for the same bytes.
+    pub fn add_key(&mut self, bytes: &[u8], key: TermKeyEvent)
+    {
+        if bytes.is_empty()
+        {
+            return;
+        }
+        self.keys.retain(|&(ref b, _)| b.as_slice() != bytes);
+        self.keys.push((bytes.to_vec(), key));
+    }
+
+    // An event from elsewhere, in order with the input fed so far.
+    pub fn push_event(&mut self, key: TermKeyEvent)
+    {
+        self.out.push_back(Event(key));
+    }
+
+    pub fn feed(&mut self, bytes: &[u8])
+    {
+        let mut plain = Vec::new();
+        for &b in bytes.iter()
+        {
+            self.byte(b, &mut plain);
+        }
+        self.push_plain(plain);
+    }
+
+    fn byte(&mut self, b: u8, plain: &mut Vec<u8>)
+    {
+        match self.state
+        {
+            Ground =>
+            {
+                if !self.partial.is_empty()
+                {
+                    if continues(self.partial.as_slice(), b)
+                    {
+                        self.partial.push(b);
+                        if self.partial.len() == ::std::str::utf8_char_width(self.partial[0])
+                        {
+                            let ch = ::std::mem::replace(&mut self.partial, Vec::new());
+                            if self.c1 && ch[0] == 0xc2 && ch[1] <= 0x9f
+                            {
+                                self.escape(ch[1] - 0x40, plain);
+                            }
+                            else
+                            {
+                                plain.push_all(ch.as_slice());
+                            }
+                        }
+                        return;
+                    }
+                    // cut short; b starts something else
+                    self.invalid(plain);
+                    self.byte(b, plain);
+                    return;
+                }
+                if b == 0x1b || self.keys.iter().any(|&(ref k, _)| k[0] == b)
+                {
+                    self.seq.clear();
+                    self.seq.push(b);
+                    self.matched = None;
+                    self.state = Pending;
+                    self.pending(plain);
+                }
+                // in UTF-8 mode, not part of a character, as it can't start one
+                else if self.c1 && b >= 0x80 && b <= 0x9f
+                {
+                    self.escape(b - 0x40, plain);
+                }
+                else if self.utf8 && b >= 0x80
+                {
+                    self.partial.push(b);
+                    if ::std::str::utf8_char_width(b) < 2
+                    {
+                        self.invalid(plain);
+                    }
+                }
+                else if b >= 0x80 && self.meta8
+                {
+                    self.meta(b & 0x7f, plain);
+                }
+                else if b >= 0x80
+                {
+                    self.emit(ByteEvent{byte: b, mods: c::X_TermKey_KeyMod::empty()}, plain);
+                }
+                else
+                {
+                    plain.push(b);
+                }
+            }
+            Pending =>
+            {
+                self.seq.push(b);
+                self.pending(plain);
+            }
+            InString =>
+            {
+                if b == 0x1b
+                {
+                    self.state = InStringEscape;
+                }
+                else if b == 0x07 || (b == 0x9c && self.c1 && !(self.utf8 && ends_mid_char(self.string.as_slice())))
+                {
+                    self.finish_string(plain);
+                    self.state = Ground;
+                }
+                else if self.string.len() < self.limit
+                {
+                    self.string.push(b);
+                }
+                else
+                {
+                    self.overflow = true;
+                }
+            }
+            InPaste =>
+            {
+                self.string.push(b);
+                if self.string.as_slice().ends_with(PASTE_END)
+                {
+                    let len = self.string.len() - PASTE_END.len();
+                    self.string.truncate(len);
+                    self.finish_paste(plain);
+                }
+                else if self.c1 && self.string.as_slice().ends_with(PASTE_END_C1)
+                {
+                    let len = self.string.len() - PASTE_END_C1.len();
+                    if !(self.utf8 && ends_mid_char(self.string.slice_to(len)))
+                    {
+                        self.string.truncate(len);
+                        self.finish_paste(plain);
+                    }
+                }
+                else if self.string.len() >= self.limit + PASTE_END.len()
+                {
+                    // too much to hold on to; pass on what there is so
+                    // far, less what might be the start of the end
+                    let keep = self.string.len() - (PASTE_END.len() - 1);
+                    let rest = self.string.slice_from(keep).to_vec();
+                    self.string.truncate(keep);
+                    let data = ::std::mem::replace(&mut self.string, rest);
+                    self.emit(PasteEvent{data: data}, plain);
+                }
+            }
+            InStringEscape =>
+            {
+                // Any ESC ends the string, but only ESC \ is a proper ST.
+                self.finish_string(plain);
+                self.state = Ground;
+                if b != b'\\'
+                {
+                    self.byte(0x1b, plain);
+                    self.byte(b, plain);
+                }
+            }
+        }
+    }
+
+    // a byte has just been added to seq
+    fn pending(&mut self, plain: &mut Vec<u8>)
+    {
+        let mut exact = None;
+        let mut longer = false;
+        for &(ref k, ref key) in self.keys.iter()
+        {
+            if k.as_slice() == self.seq.as_slice()
+            {
+                exact = Some(key.clone());
+            }
+            else if k.len() > self.seq.len() && k.as_slice().starts_with(self.seq.as_slice())
+            {
+                longer = true;
+            }
+        }
+        match exact
+        {
+            Some(key) => { self.matched = Some((self.seq.len(), key)); }
+            None => {}
+        }
+        if self.seq.as_slice() == PASTE_START
+        {
+            let done = ::std::mem::replace(plain, Vec::new());
+            self.push_plain(done);
+            self.seq.clear();
+            self.matched = None;
+            self.string.clear();
+            self.state = InPaste;
+            return;
+        }
+        if longer || PASTE_START.starts_with(self.seq.as_slice())
+        {
+            return;
+        }
+        if self.seq.len() == 1 && self.seq[0] == 0x1b
+        {
+            // might be a string introducer
+            return;
+        }
+        if self.seq.len() == 2 && self.seq[0] == 0x1b && self.seq[1] >= 0x80 && !self.utf8 && self.matched.is_none()
+        {
+            let byte = self.seq[1];
+            self.seq.clear();
+            self.state = Ground;
+            if self.c1 && byte <= 0x9f
+            {
+                // not Alt, but ESC and then a control of its own
+                self.escape_key(plain);
+                self.byte(byte, plain);
+                return;
+            }
+            self.emit(ByteEvent{byte: byte, mods: c::TERMKEY_KEYMOD_ALT}, plain);
+            return;
+        }
+        // and in UTF-8, ESC before a C1 control, which takes two bytes there
+        if self.seq.len() >= 2 && self.seq[0] == 0x1b && self.seq[1] == 0xc2 && self.utf8 && self.c1 && self.matched.is_none()
+        {
+            if self.seq.len() == 2
+            {
+                return;
+            }
+            let byte = self.seq[2];
+            if byte >= 0x80 && byte <= 0x9f
+            {
+                self.seq.clear();
+                self.state = Ground;
+                self.escape_key(plain);
+                self.escape(byte - 0x40, plain);
+                return;
+            }
+        }
+        if self.seq.len() == 2 && self.seq[0] == 0x1b && (self.seq[1] == b'P' || self.seq[1] == b']')
+        {
+            let done = ::std::mem::replace(plain, Vec::new());
+            self.push_plain(done);
+            self.string.clear();
+            self.overflow = false;
+            self.kind = if self.seq[1] == b'P' { Dcs } else { Osc };
+            self.state = InString;
+            return;
+        }
+        self.unpend(plain);
+    }
+
+    // seq can't get any longer; take the longest learned key it starts
+    // with, or else its first byte as-is, and go again with the rest
+    fn unpend(&mut self, plain: &mut Vec<u8>)
+    {
+        let seq = ::std::mem::replace(&mut self.seq, Vec::new());
+        let used = match self.matched.take()
+        {
+            Some((len, key)) =>
+            {
+                self.emit(key, plain);
+                len
+            }
+            None =>
+            {
+                plain.push(seq[0]);
+                1
+            }
+        };
+        self.state = Ground;
+        for &b in seq.slice_from(used).iter()
+        {
+            self.byte(b, plain);
+        }
+    }
+
+    fn finish_string(&mut self, plain: &mut Vec<u8>)
+    {
+        let body = ::std::mem::replace(&mut self.string, Vec::new());
+        if self.overflow
+        {
+            return;
+        }
+        let key = match self.kind
+        {
+            Dcs => TermKeyEvent::from_dcs(body.as_slice()),
+            Osc => TermKeyEvent::from_osc(body.as_slice()),
+        };
+        self.emit(key, plain);
+    }
+
+    fn finish_paste(&mut self, plain: &mut Vec<u8>)
+    {
+        let data = ::std::mem::replace(&mut self.string, Vec::new());
+        self.emit(PasteEvent{data: data}, plain);
+        self.state = Ground;
+    }
+
+    fn invalid(&mut self, plain: &mut Vec<u8>)
+    {
+        let bytes = ::std::mem::replace(&mut self.partial, Vec::new());
+        if self.meta8 && bytes.len() == 1
+        {
+            self.meta(bytes[0] & 0x7f, plain);
+            return;
+        }
+        self.emit(InvalidUtf8Event{bytes: bytes}, plain);
+    }
+
+    // a lone ESC, as libtermkey would give it
+    fn escape_key(&mut self, plain: &mut Vec<u8>)
+    {
+        self.emit(KeySymEvent{sym: c::TERMKEY_SYM_ESCAPE, mods: c::X_TermKey_KeyMod::empty()}, plain);
+    }
+
+    // the 7-bit form of a C1 control
+    fn escape(&mut self, b: u8, plain: &mut Vec<u8>)
+    {
+        self.byte(0x1b, plain);
+        self.byte(b, plain);
+    }
+
+    // a high-bit Alt; ESC and b would be the start of a CSI, SS3, DCS
+    // or OSC for these, so they are keys here
+    fn meta(&mut self, b: u8, plain: &mut Vec<u8>)
+    {
+        if b == b'[' || b == b'O' || b == b'P' || b == b']'
+        {
+            self.emit(keymap::unicode(b as char, c::TERMKEY_KEYMOD_ALT), plain);
+        }
+        else
+        {
+            plain.push(0x1b);
+            plain.push(b);
+        }
+    }
+
+    // an event goes after the plain bytes before it
+    fn emit(&mut self, key: TermKeyEvent, plain: &mut Vec<u8>)
+    {
+        let done = ::std::mem::replace(plain, Vec::new());
+        self.push_plain(done);
+        self.out.push_back(Event(key));
+    }
+
+    fn push_plain(&mut self, plain: Vec<u8>)
+    {
+        if !plain.is_empty()
+        {
+            self.out.push_back(Bytes(plain));
+        }
+    }
+
+    // Bytes filtered but not yet taken for libtermkey.
+    pub fn waiting(&self) -> uint
+    {
+        self.out.iter().map(|chunk| match *chunk
+        {
+            Bytes(ref bytes) => bytes.len(),
+            Event(_) => 0,
+        }).fold(0, |a, b| a + b)
+    }
+
+    // Is there a partial sequence that might still turn into something?
+    pub fn is_holding(&self) -> bool
+    {
+        self.state != Ground || !self.partial.is_empty()
+    }
+
+    // A paste can't be mistaken for anything else, so it is worth
+    // waiting for however long it takes (or until EOF).
+    pub fn in_paste(&self) -> bool
+    {
+        self.state == InPaste
+    }
+
+    // Give up waiting for the rest of a partial sequence, e.g. a lone
+    // ESC key, or Alt-P, which is indistinguishable from a DCS introducer.
+    pub fn release(&mut self)
+    {
+        let mut plain = Vec::new();
+        // a learned key might have been followed by the start of a string
+        loop
+        {
+            match self.state
+            {
+                Ground if !self.partial.is_empty() => self.invalid(&mut plain),
+                Ground => break,
+                Pending => self.unpend(&mut plain),
+                // only at EOF; what there is of it
+                InPaste => self.finish_paste(&mut plain),
+                InString | InStringEscape =>
+                {
+                    // the body is filtered again, as if there had been
+                    // no string; only the introducer goes straight on
+                    let escaped = self.state == InStringEscape;
+                    let body = ::std::mem::replace(&mut self.string, Vec::new());
+                    self.state = Ground;
+                    plain.push(0x1b);
+                    plain.push(if self.kind == Dcs { b'P' } else { b']' });
+                    for &b in body.iter()
+                    {
+                        self.byte(b, &mut plain);
+                    }
+                    if escaped
+                    {
+                        self.byte(0x1b, &mut plain);
+                    }
+                }
+            }
+        }
+        self.push_plain(plain);
+    }
+
+    // The next up to max bytes, if bytes are next.
+    pub fn take_bytes(&mut self, max: uint) -> Option<Vec<u8>>
+    {
+        loop
+        {
+            match self.out.pop_front()
+            {
+                Some(Bytes(mut bytes)) =>
+                {
+                    if bytes.is_empty()
+                    {
+                        continue;
+                    }
+                    if bytes.len() > max
+                    {
+                        let rest = bytes.slice_from(max).to_vec();
+                        bytes.truncate(max);
+                        self.out.push_front(Bytes(rest));
+                    }
+                    return Some(bytes);
+                }
+                Some(chunk) =>
+                {
+                    self.out.push_front(chunk);
+                    return None;
+                }
+                None => return None,
+            }
+        }
+    }
+
+    // The next event, if an event is next.
+    pub fn take_event(&mut self) -> Option<TermKeyEvent>
+    {
+        match self.out.pop_front()
+        {
+            Some(Event(key)) => Some(key),
+            Some(chunk) =>
+            {
+                self.out.push_front(chunk);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+// Split the body of a DCS string into its numeric parameters, its
+// private-marker/intermediate/final bytes, and the data string.
+pub fn split_dcs<'a>(body: &'a [u8]) -> (Vec<int>, String, &'a [u8])
+{
+    let mut params = Vec::new();
+    let mut intermediates = String::new();
+    let mut i = 0u;
+
+    let mut cur: Option<int> = None;
+    while i < body.len()
+    {
+        let b = body[i];
+        match b
+        {
+            b'0' ... b'9' =>
+            {
+                let d = (b - b'0') as int;
+                cur = Some(cur.unwrap_or(0) * 10 + d);
+            }
+            b';' =>
+            {
+                params.push(cur.unwrap_or(0));
+                cur = None;
+            }
+            // private markers
+            b'<' ... b'?' =>
+            {
+                intermediates.push(b as char);
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    match cur
+    {
+        Some(p) => params.push(p),
+        None => {}
+    }
+    while i < body.len() && body[i] >= 0x20 && body[i] <= 0x2f
+    {
+        intermediates.push(body[i] as char);
+        i += 1;
+    }
+    // the final byte
+    if i < body.len() && body[i] >= 0x40 && body[i] <= 0x7e
+    {
+        intermediates.push(body[i] as char);
+        i += 1;
+    }
+    (params, intermediates, body.slice_from(i))
+}
+
+// Split the body of an OSC string at the first ';' into the command
+// number and the rest, or -1 and everything if it doesn't start with one.
+pub fn split_osc<'a>(body: &'a [u8]) -> (int, &'a [u8])
+{
+    let mut number = 0;
+    let mut i = 0u;
+    while i < body.len() && body[i] >= b'0' && body[i] <= b'9'
+    {
+        number = number * 10 + (body[i] - b'0') as int;
+        i += 1;
+    }
+    if i == 0
+    {
+        return (-1, body);
+    }
+    if i == body.len()
+    {
+        return (number, body.slice_from(i));
+    }
+    if body[i] != b';'
+    {
+        return (-1, body);
+    }
+    (number, body.slice_from(i + 1))
+}
+
+// Can b come next in the character that starts with partial?
+fn continues(partial: &[u8], b: u8) -> bool
+{
+    if partial.len() > 1
+    {
+        return b >= 0x80 && b <= 0xbf;
+    }
+    // the second byte also rules out overlong forms, surrogates, and
+    // anything past U+10FFFF
+    match partial[0]
+    {
+        0xe0 => b >= 0xa0 && b <= 0xbf,
+        0xed => b >= 0x80 && b <= 0x9f,
+        0xf0 => b >= 0x90 && b <= 0xbf,
+        0xf4 => b >= 0x80 && b <= 0x8f,
+        _ => b >= 0x80 && b <= 0xbf,
+    }
+}
+
+// Does s end partway through a UTF-8 character?
+fn ends_mid_char(s: &[u8]) -> bool
+{
+    let n = s.iter().rev().take(3).take_while(|&&b| b >= 0x80 && b <= 0xbf).count();
+    if n >= s.len()
+    {
+        return false;
+    }
+    ::std::str::utf8_char_width(s[s.len() - n - 1]) > n + 1
+}
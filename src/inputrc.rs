@@ -0,0 +1,305 @@
+// Readline's inputrc: key bindings such as
+//
+//     "\C-x\C-r": re-read-init-file
+//     "\e[A": history-search-backward
+//     Meta-Rubout: backward-kill-word
+//
+// Quoted key sequences are the bytes a terminal sends, so they are
+// decoded the same way as real input, by a new_abstract TermKey.
+//
+// Directives ($if, $include, ...) and variables (set ...) are skipped,
+// and bindings inside $if blocks are kept as if the test had passed.
+
+use c;
+use keymap;
+use {TermKey, TermKeyEvent, KeySymEvent, Key, Again};
+
+#[deriving(PartialEq, Clone, Show)]
+pub enum Action
+{
+    // a readline function name
+    Function(String),
+    // "text" to insert, with escapes decoded
+    Macro(Vec<u8>),
+}
+
+#[deriving(PartialEq, Clone)]
+pub struct Binding
+{
+    pub keys: Vec<TermKeyEvent>,
+    pub action: Action,
+}
+
+// The escapes in a quoted key sequence or macro.
+pub fn decode_escapes(s: &str) -> Option<Vec<u8>>
+{
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0u;
+    while i < bytes.len()
+    {
+        let (b, len) = match decode_one(bytes.slice_from(i))
+        {
+            Some(x) => x,
+            None => return None,
+        };
+        out.push_all(b.as_slice());
+        i += len;
+    }
+    Some(out)
+}
+
+// one character, escaped or not: (bytes, length used)
+fn decode_one(s: &[u8]) -> Option<(Vec<u8>, uint)>
+{
+    if s[0] != b'\\'
+    {
+        return Some((vec![s[0]], 1));
+    }
+    if s.len() < 2
+    {
+        return None;
+    }
+    if s.len() >= 3 && s[2] == b'-' && (s[1] == b'C' || s[1] == b'M')
+    {
+        if s.len() < 4
+        {
+            return None;
+        }
+        let (inner, len) = match decode_one(s.slice_from(3))
+        {
+            Some((ref b, len)) if b.len() >= 1 => (b.clone(), len),
+            _ => return None,
+        };
+        let last = inner[inner.len() - 1];
+        let mut out = inner.slice_to(inner.len() - 1).to_vec();
+        if s[1] == b'C'
+        {
+            out.push(if last == b'?' { 0x7f } else { last & 0x1f });
+            return Some((out, 3 + len));
+        }
+        // meta is ESC first, as with convert-meta off
+        let mut meta = vec![0x1b];
+        meta.push_all(inner.as_slice());
+        return Some((meta, 3 + len));
+    }
+    let b = match s[1]
+    {
+        b'e' => 0x1b,
+        b'a' => 0x07,
+        b'b' => 0x08,
+        b'd' => 0x7f,
+        b'f' => 0x0c,
+        b'n' => 0x0a,
+        b'r' => 0x0d,
+        b't' => 0x09,
+        b'v' => 0x0b,
+        b'0' ... b'7' =>
+        {
+            let mut n = 0u;
+            let mut len = 1;
+            while len < 4 && len < s.len() && s[len] >= b'0' && s[len] <= b'7'
+            {
+                n = n * 8 + (s[len] - b'0') as uint;
+                len += 1;
+            }
+            return Some((vec![n as u8], len));
+        }
+        b'x' =>
+        {
+            let mut n = 0u;
+            let mut len = 2;
+            while len < 4 && len < s.len() && (s[len] as char).is_digit_radix(16)
+            {
+                n = n * 16 + (s[len] as char).to_digit(16).unwrap();
+                len += 1;
+            }
+            if len == 2
+            {
+                return None;
+            }
+            return Some((vec![n as u8], len));
+        }
+        // \\ \" \' and anything else stand for themselves
+        other => other,
+    };
+    Some((vec![b], 2))
+}
+
+// Decodes inputrc lines with a TermKey of its own.
+pub struct InputrcParser
+{
+    tk: TermKey,
+}
+
+impl InputrcParser
+{
+    // term and flags as for new_abstract; they should match the
+    // TermKey the bindings are for, so that the keys compare equal.
+    pub fn new(term: &str, flags: c::X_TermKey_Flag) -> InputrcParser
+    {
+        InputrcParser{tk: TermKey::new_abstract(term, flags)}
+    }
+
+    // The keys in a quoted sequence, e.g. "\e[A" is Up.
+    pub fn parse_keyseq(&mut self, s: &str) -> Option<Vec<TermKeyEvent>>
+    {
+        let bytes = match decode_escapes(s)
+        {
+            Some(bytes) => bytes,
+            None => return None,
+        };
+        // anything longer than the buffer is no key sequence
+        if bytes.len() > self.tk.get_buffer_remaining()
+        {
+            return None;
+        }
+        self.tk.push_bytes(bytes.as_slice());
+        let mut keys = Vec::new();
+        loop
+        {
+            match self.tk.getkey()
+            {
+                Key(key) => keys.push(key),
+                // the end of the string is the end of the sequence
+                Again => match self.tk.getkey_force()
+                {
+                    Key(key) => keys.push(key),
+                    _ => break,
+                },
+                _ => break,
+            }
+        }
+        if keys.is_empty() { None } else { Some(keys) }
+    }
+
+    // A key name, e.g. Control-u or Meta-Rubout.
+    pub fn parse_keyname(&mut self, s: &str) -> Option<TermKeyEvent>
+    {
+        let mut mods = c::X_TermKey_KeyMod::empty();
+        let mut rest = s;
+        loop
+        {
+            let lower = keymap::lower(rest);
+            let lower = lower.as_slice();
+            if lower.starts_with("control-") { mods.insert(c::TERMKEY_KEYMOD_CTRL); rest = rest.slice_from(8); }
+            else if lower.starts_with("c-") { mods.insert(c::TERMKEY_KEYMOD_CTRL); rest = rest.slice_from(2); }
+            else if lower.starts_with("meta-") { mods.insert(c::TERMKEY_KEYMOD_ALT); rest = rest.slice_from(5); }
+            else if lower.starts_with("m-") { mods.insert(c::TERMKEY_KEYMOD_ALT); rest = rest.slice_from(2); }
+            else { break; }
+        }
+        let sym = match keymap::lower(rest).as_slice()
+        {
+            "rubout" | "del" => c::TERMKEY_SYM_DEL,
+            "escape" | "esc" => c::TERMKEY_SYM_ESCAPE,
+            "return" | "ret" | "newline" => c::TERMKEY_SYM_ENTER,
+            "tab" => c::TERMKEY_SYM_TAB,
+            "space" | "spc" => return Some(keymap::space(&mut self.tk, mods)),
+            "lfd" => return Some(keymap::unicode('j', mods | c::TERMKEY_KEYMOD_CTRL)),
+            _ =>
+            {
+                if rest.char_len() != 1
+                {
+                    return None;
+                }
+                let ch = rest.char_at(0);
+                // readline's Control-X is Control-x
+                let ch = if mods.contains(c::TERMKEY_KEYMOD_CTRL) { ch.to_lowercase() } else { ch };
+                return Some(keymap::unicode(ch, mods));
+            }
+        };
+        Some(KeySymEvent{sym: sym, mods: mods})
+    }
+
+    // One line; None for blank lines, comments, directives, variables,
+    // and anything that doesn't parse.
+    pub fn parse_line(&mut self, line: &str) -> Option<Binding>
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") || line.starts_with("$") || line.starts_with("set ")
+        {
+            return None;
+        }
+
+        let (keys, rest) = if line.starts_with("\"")
+        {
+            let end = match closing_quote(line)
+            {
+                Some(end) => end,
+                None => return None,
+            };
+            let rest = line.slice_from(end + 1).trim_left();
+            if !rest.starts_with(":")
+            {
+                return None;
+            }
+            (self.parse_keyseq(line.slice(1, end)), rest.slice_from(1))
+        }
+        else
+        {
+            let colon = match line.find(':')
+            {
+                Some(colon) => colon,
+                None => return None,
+            };
+            (self.parse_keyname(line.slice_to(colon).trim()).map(|key| vec![key]), line.slice_from(colon + 1))
+        };
+        let keys = match keys
+        {
+            Some(keys) => keys,
+            None => return None,
+        };
+
+        let rest = rest.trim();
+        let action = if rest.starts_with("\"") || rest.starts_with("'")
+        {
+            let end = match closing_quote(rest)
+            {
+                Some(end) => end,
+                None => return None,
+            };
+            match decode_escapes(rest.slice(1, end))
+            {
+                Some(text) => Macro(text),
+                None => return None,
+            }
+        }
+        else
+        {
+            match rest.words().next()
+            {
+                Some(name) => Function(name.to_string()),
+                None => return None,
+            }
+        };
+        Some(Binding{keys: keys, action: action})
+    }
+
+    // A whole file's worth.
+    pub fn parse(&mut self, text: &str) -> Vec<Binding>
+    {
+        text.lines().filter_map(|line| self.parse_line(line)).collect()
+    }
+}
+
+// the index of the quote matching s[0], skipping escaped ones
+fn closing_quote(s: &str) -> Option<uint>
+{
+    let bytes = s.as_bytes();
+    let quote = bytes[0];
+    let mut i = 1;
+    while i < bytes.len()
+    {
+        if bytes[i] == b'\\'
+        {
+            i += 2;
+            continue;
+        }
+        if bytes[i] == quote
+        {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,175 @@
+// Emacs kbd notation: "C-x C-f", "M-<up>", "<f5>", "C-M-%", "RET".
+//
+// Only the modifiers libtermkey has (C-, M- and S-) are understood;
+// s-, H- and A- (super, hyper, alt) fail to parse.
+
+use c;
+use keymap;
+use {TermKey, TermKeyEvent, UnicodeEvent, FunctionEvent, KeySymEvent};
+
+// Emacs names for keys that libtermkey calls something else.
+static ALIASES: &'static [(&'static str, c::TermKeySym)] = &[
+    ("return", c::TERMKEY_SYM_ENTER),
+    ("escape", c::TERMKEY_SYM_ESCAPE),
+    ("prior", c::TERMKEY_SYM_PAGEUP),
+    ("next", c::TERMKEY_SYM_PAGEDOWN),
+    ("deletechar", c::TERMKEY_SYM_DELETE),
+    ("insertchar", c::TERMKEY_SYM_INSERT),
+    ("kp-enter", c::TERMKEY_SYM_KPENTER),
+    ("kp-add", c::TERMKEY_SYM_KPPLUS),
+    ("kp-subtract", c::TERMKEY_SYM_KPMINUS),
+    ("kp-multiply", c::TERMKEY_SYM_KPMULT),
+    ("kp-divide", c::TERMKEY_SYM_KPDIV),
+    ("kp-separator", c::TERMKEY_SYM_KPCOMMA),
+    ("kp-decimal", c::TERMKEY_SYM_KPPERIOD),
+    ("kp-equal", c::TERMKEY_SYM_KPEQUALS),
+];
+
+// The whole string, as space-separated keys.
+pub fn parse(tk: &mut TermKey, s: &str) -> Option<Vec<TermKeyEvent>>
+{
+    let mut keys = Vec::new();
+    for word in s.words()
+    {
+        if !parse_word(tk, word, &mut keys)
+        {
+            return None;
+        }
+    }
+    if keys.is_empty() { None } else { Some(keys) }
+}
+
+fn parse_word(tk: &mut TermKey, word: &str, keys: &mut Vec<TermKeyEvent>) -> bool
+{
+    let mut mods = c::X_TermKey_KeyMod::empty();
+    let mut rest = word;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-'
+    {
+        match rest.as_bytes()[0]
+        {
+            b'C' => mods.insert(c::TERMKEY_KEYMOD_CTRL),
+            b'M' => mods.insert(c::TERMKEY_KEYMOD_ALT),
+            b'S' => mods.insert(c::TERMKEY_KEYMOD_SHIFT),
+            b's' | b'H' | b'A' => return false,
+            _ => break,
+        }
+        rest = rest.slice_from(2);
+    }
+
+    if rest.len() > 2 && rest.starts_with("<") && rest.ends_with(">")
+    {
+        return match named_key(tk, rest.slice(1, rest.len() - 1), mods)
+        {
+            Some(key) => { keys.push(key); true }
+            None => false,
+        };
+    }
+
+    let key = match rest
+    {
+        "RET" => KeySymEvent{sym: c::TERMKEY_SYM_ENTER, mods: mods},
+        "TAB" => KeySymEvent{sym: c::TERMKEY_SYM_TAB, mods: mods},
+        "ESC" => KeySymEvent{sym: c::TERMKEY_SYM_ESCAPE, mods: mods},
+        "DEL" => KeySymEvent{sym: c::TERMKEY_SYM_DEL, mods: mods},
+        "SPC" => keymap::space(tk, mods),
+        "LFD" => keymap::unicode('j', mods | c::TERMKEY_KEYMOD_CTRL),
+        "NUL" => keymap::space(tk, mods | c::TERMKEY_KEYMOD_CTRL),
+        _ =>
+        {
+            if rest.char_len() == 1
+            {
+                keymap::unicode(rest.char_at(0), mods)
+            }
+            else if mods.is_empty()
+            {
+                // "abc" is three keys
+                for ch in rest.chars()
+                {
+                    keys.push(keymap::unicode(ch, mods));
+                }
+                return true;
+            }
+            else
+            {
+                return false;
+            }
+        }
+    };
+    keys.push(key);
+    true
+}
+
+// what goes between < and >
+fn named_key(tk: &mut TermKey, name: &str, mods: c::X_TermKey_KeyMod) -> Option<TermKeyEvent>
+{
+    match keymap::function_number(name)
+    {
+        Some(num) => return Some(FunctionEvent{num: num, mods: mods}),
+        None => {}
+    }
+    for &(alias, sym) in ALIASES.iter()
+    {
+        if name == alias
+        {
+            return Some(KeySymEvent{sym: sym, mods: mods});
+        }
+    }
+    if name == "space"
+    {
+        return Some(keymap::space(tk, mods));
+    }
+    keymap::sym_by_name(tk, name).map(|sym| KeySymEvent{sym: sym, mods: mods})
+}
+
+// The other way: e.g. [Ctrl-x, Ctrl-f] is "C-x C-f". Keys with no kbd
+// name (mouse events, replies) are left out.
+pub fn format(tk: &mut TermKey, keys: &[TermKeyEvent]) -> String
+{
+    let words: Vec<String> = keys.iter().filter_map(|key| format_key(tk, key)).collect();
+    words.connect(" ")
+}
+
+pub fn format_key(tk: &mut TermKey, key: &TermKeyEvent) -> Option<String>
+{
+    let (mods, name) = match *key
+    {
+        UnicodeEvent{codepoint: ' ', mods, ..} => (mods, "SPC".to_string()),
+        UnicodeEvent{codepoint, mods, ..} => (mods, codepoint.to_string()),
+        FunctionEvent{num, mods} => (mods, format!("<f{}>", num)),
+        KeySymEvent{sym, mods} =>
+        {
+            let ctrl = mods.contains(c::TERMKEY_KEYMOD_CTRL);
+            let name = match sym
+            {
+                c::TERMKEY_SYM_SPACE => "SPC".to_string(),
+                c::TERMKEY_SYM_DEL => "DEL".to_string(),
+                // C-RET would be C-m
+                c::TERMKEY_SYM_ENTER if !ctrl => "RET".to_string(),
+                c::TERMKEY_SYM_TAB if !ctrl => "TAB".to_string(),
+                c::TERMKEY_SYM_ESCAPE if !ctrl => "ESC".to_string(),
+                _ =>
+                {
+                    let mut alias = None;
+                    for &(a, s) in ALIASES.iter()
+                    {
+                        if s == sym
+                        {
+                            alias = Some(a.to_string());
+                            break;
+                        }
+                    }
+                    let name = alias.unwrap_or_else(|| keymap::lower(tk.get_keyname(sym)));
+                    format!("<{}>", name)
+                }
+            };
+            (mods, name)
+        }
+        _ => return None,
+    };
+    let mut s = String::new();
+    if mods.contains(c::TERMKEY_KEYMOD_CTRL) { s.push_str("C-"); }
+    if mods.contains(c::TERMKEY_KEYMOD_ALT) { s.push_str("M-"); }
+    if mods.contains(c::TERMKEY_KEYMOD_SHIFT) { s.push_str("S-"); }
+    s.push_str(name.as_slice());
+    Some(s)
+}
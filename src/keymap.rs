@@ -0,0 +1,83 @@
+// Pieces shared by the key notation parsers (kbd.rs and friends), so
+// they all agree with libtermkey, and each other, about key names.
+
+use c;
+use {TermKey, TermKeyEvent, UnicodeEvent, KeySymEvent, Utf8Char};
+
+// A key as a notation names it. Shift with a letter (but not with Ctrl,
+// where the terminal reports Shift itself) is the capital letter, as
+// the terminal sends it.
+pub fn unicode(codepoint: char, mods: c::X_TermKey_KeyMod) -> TermKeyEvent
+{
+    let (codepoint, mods) = if mods.contains(c::TERMKEY_KEYMOD_SHIFT) && !mods.contains(c::TERMKEY_KEYMOD_CTRL)
+            && codepoint.is_lowercase() && codepoint.to_uppercase() != codepoint
+    {
+        (codepoint.to_uppercase(), mods - c::TERMKEY_KEYMOD_SHIFT)
+    }
+    else
+    {
+        (codepoint, mods)
+    };
+    UnicodeEvent{codepoint: codepoint, mods: mods, utf8: Utf8Char::from_char(codepoint)}
+}
+
+// Space is a symbol only with TERMKEY_FLAG_SPACESYMBOL, so what the
+// notations call Space has to depend on tk, to match getkey.
+pub fn space(tk: &mut TermKey, mods: c::X_TermKey_KeyMod) -> TermKeyEvent
+{
+    if tk.get_flags().contains(c::TERMKEY_FLAG_SPACESYMBOL)
+    {
+        KeySymEvent{sym: c::TERMKEY_SYM_SPACE, mods: mods}
+    }
+    else
+    {
+        unicode(' ', mods)
+    }
+}
+
+// Every symbol libtermkey has a name for.
+pub fn all_syms() -> Vec<c::TermKeySym>
+{
+    let first = c::TERMKEY_SYM_BACKSPACE as c::c_int;
+    let last = c::TERMKEY_SYM_KPEQUALS as c::c_int;
+    range(first, last + 1).map(|i| unsafe { ::std::mem::transmute::<c::c_int, c::TermKeySym>(i) }).collect()
+}
+
+// keyname2sym, but ignoring case ("pageup", "PAGEUP").
+pub fn sym_by_name(tk: &mut TermKey, name: &str) -> Option<c::TermKeySym>
+{
+    let sym = tk.keyname2sym(name);
+    if sym != c::TERMKEY_SYM_UNKNOWN
+    {
+        return Some(sym);
+    }
+    let name = lower(name);
+    for sym in all_syms().into_iter()
+    {
+        if lower(tk.get_keyname(sym)) == name
+        {
+            return Some(sym);
+        }
+    }
+    None
+}
+
+// "F5" or "f5" as a function key number
+pub fn function_number(name: &str) -> Option<int>
+{
+    if name.len() < 2 || !(name.starts_with("f") || name.starts_with("F"))
+    {
+        return None;
+    }
+    match from_str::<int>(name.slice_from(1))
+    {
+        Some(num) if num >= 1 && num <= 63 => Some(num),
+        _ => None,
+    }
+}
+
+// for comparing key names
+pub fn lower(s: &str) -> String
+{
+    s.chars().map(|c| c.to_lowercase()).collect()
+}
@@ -4,13 +4,67 @@
 #![feature(link_args)]
 
 extern crate libc;
+extern crate serialize;
+extern crate time;
+
+use std::collections::{Deque, RingBuf};
+
 mod bitset_macro;
 pub mod c;
+pub mod charset;
+mod clipboard;
+pub mod color;
+pub mod da;
+pub mod fuzz;
 mod generated_link;
+mod input;
+pub mod inputrc;
+pub mod kbd;
+pub mod keymap;
+pub mod lineedit;
+pub mod modal;
+pub mod mode;
+mod poll;
+pub mod reader;
+mod resize;
+mod signal;
+mod suspend;
+pub mod telnet;
+pub mod termios;
+pub mod tmux;
+pub mod tty;
+pub mod vscode;
+pub mod xtgettcap;
 
+// The fd is read here rather than by libtermkey, so that input
+// libtermkey doesn't understand can be picked out first (see input.rs).
 pub struct TermKey
 {
     tk: *mut c::TermKey,
+    // keys that arrived while a query was waiting for its reply
+    queue: RingBuf<TermKeyEvent>,
+    // input that has been read but not yet given to libtermkey
+    input: input::InputFilter,
+    // read() has returned 0
+    eof: bool,
+    // SIGWINCH is turned into ResizeEvent (see resize.rs)
+    winch: bool,
+    // for any signals caught (see signal.rs)
+    signals: Option<signal::Watcher>,
+    // modes set with enable_mode (see mode.rs)
+    modes: Vec<mode::Mode>,
+    // SIGTSTP and SIGCONT are handled (see suspend.rs)
+    job_control: bool,
+    // None leaves it to libtermkey (see termios.rs)
+    plain_keys: Option<termios::PlainKeys>,
+    // the terminal before the last start
+    orig_termios: Option<termios::Termios>,
+    // read from this instead of the fd (see reader.rs)
+    reader: Option<Box<std::io::Reader + 'static>>,
+    // for ByteEvents (see charset.rs)
+    charset: Option<Box<charset::Charset + 'static>>,
+    // for advisereadable, kept between calls
+    readbuf: Vec<u8>,
 }
 
 impl TermKey
@@ -20,12 +74,16 @@ impl TermKey
         unsafe
         {
             c::TERMKEY_CHECK_VERSION();
+            // termkey_new also starts
+            let orig = termios::get(fd);
             let tk = c::termkey_new(fd, std::mem::transmute(flags));
             if tk as uint == 0
             {
                 panic!()
             }
-            TermKey{tk: tk}
+            let mut tk = TermKey::wrap(tk);
+            tk.orig_termios = orig;
+            tk
         }
     }
     pub fn new_abstract(term: &str, flags: c::X_TermKey_Flag) -> TermKey
@@ -33,23 +91,39 @@ impl TermKey
         unsafe
         {
             c::TERMKEY_CHECK_VERSION();
-            term.with_c_str(|c_buffer|
+            c_prefix(term).with_c_str(|c_buffer|
             {
                 let tk = c::termkey_new_abstract(c_buffer, std::mem::transmute(flags));
                 if tk as uint == 0
                 {
                     panic!()
                 }
-                TermKey{tk: tk}
+                TermKey::wrap(tk)
             })
         }
     }
+
+    fn wrap(tk: *mut c::TermKey) -> TermKey
+    {
+        let mut tk = TermKey{tk: tk, queue: RingBuf::new(), input: input::InputFilter::new(), eof: false,
+                winch: false, signals: None, modes: Vec::new(), job_control: false,
+                plain_keys: None, orig_termios: None, reader: None, charset: None, readbuf: Vec::new()};
+        // libtermkey picks one from the locale if neither flag was given
+        let utf8 = tk.get_flags().contains(c::TERMKEY_FLAG_UTF8);
+        tk.input.set_utf8(utf8);
+        tk
+    }
 }
 
 impl Drop for TermKey
 {
     fn drop(&mut self)
     {
+        self.disable_job_control();
+        if self.winch
+        {
+            signal::release(signal::SIGWINCH);
+        }
         unsafe
         {
             c::termkey_destroy(self.tk)
@@ -61,6 +135,11 @@ impl TermKey
 {
     pub fn start(&mut self) //-> Result<(), ()>
     {
+        if !self.is_started()
+        {
+            let fd = self.get_fd() as c::c_int;
+            self.orig_termios = termios::get(fd);
+        }
         unsafe
         {
             if c::termkey_start(self.tk) == 0
@@ -68,6 +147,7 @@ impl TermKey
                 panic!()
             }
         }
+        self.apply_plain_keys();
     }
     pub fn stop(&mut self) //-> Result<(), ()>
     {
@@ -86,6 +166,20 @@ impl TermKey
             c::termkey_is_started(self.tk) != 0
         }
     }
+
+    fn apply_plain_keys(&mut self)
+    {
+        if self.get_flags().contains(c::TERMKEY_FLAG_NOTERMIOS)
+        {
+            return;
+        }
+        let fd = self.get_fd() as c::c_int;
+        match (self.plain_keys, &self.orig_termios)
+        {
+            (Some(keys), &Some(ref orig)) => termios::apply(fd, orig, keys),
+            _ => {}
+        }
+    }
 }
 
 impl TermKey
@@ -111,6 +205,7 @@ impl TermKey
         {
             c::termkey_set_flags(self.tk, std::mem::transmute(newflags))
         }
+        self.input.set_utf8(newflags.contains(c::TERMKEY_FLAG_UTF8));
     }
 
     pub fn get_waittime(&mut self) -> int
@@ -165,12 +260,62 @@ impl TermKey
             c::termkey_get_buffer_remaining(self.tk) as uint
         }
     }
+
+    // OSC and DCS strings longer than this are dropped, and pastes are
+    // passed on in pieces about this long
+    pub fn get_string_limit(&mut self) -> uint
+    {
+        self.input.get_limit()
+    }
+    pub fn set_string_limit(&mut self, limit: uint)
+    {
+        self.input.set_limit(limit)
+    }
+
+    // Decode bytes as key from now on, ahead of libtermkey's own table;
+    // see xtgettcap.rs for where these usually come from.
+    pub fn add_key_sequence(&mut self, bytes: &[u8], key: TermKeyEvent)
+    {
+        self.input.add_key(bytes, key)
+    }
+
+    // 8-bit C1 controls (0x80 to 0x9f, e.g. 0x9b for CSI and 0x8f for
+    // SS3) are read as ESC and the 7-bit form. In UTF-8 mode, that is
+    // a lone C1 byte, or one encoded as UTF-8 (0xc2 0x9b).
+    pub fn get_8bit_controls(&mut self) -> bool
+    {
+        self.input.get_c1()
+    }
+    pub fn set_8bit_controls(&mut self, c1: bool)
+    {
+        self.input.set_c1(c1)
+    }
+
+    // A byte with the high bit set is Alt and the byte without it, as
+    // xterm sends with metaSendsEscape off. In UTF-8 mode, only bytes
+    // that aren't part of a character are taken that way. Where both
+    // this and 8-bit controls are on, 0x80 to 0x9f are controls.
+    pub fn get_high_bit_alt(&mut self) -> bool
+    {
+        self.input.get_meta8()
+    }
+    pub fn set_high_bit_alt(&mut self, meta8: bool)
+    {
+        self.input.set_meta8(meta8)
+    }
 }
 
 pub struct Utf8Char
 {
     pub bytes: [c::c_char, ..7],
 }
+impl Clone for Utf8Char
+{
+    fn clone(&self) -> Utf8Char
+    {
+        Utf8Char{bytes: self.bytes}
+    }
+}
 impl PartialEq for Utf8Char
 {
     fn eq(&self, other: &Utf8Char) -> bool
@@ -188,19 +333,61 @@ impl PartialOrd for Utf8Char
 
 impl Utf8Char
 {
-    pub fn s<'a>(&'a self) -> &'a str
+    pub fn from_char(codepoint: char) -> Utf8Char
     {
+        let mut buf = [0u8, ..7];
+        codepoint.encode_utf8(buf.as_mut_slice());
         unsafe
         {
-            let bytes: &[c::c_char] = self.bytes;
-            let bytes: &[u8] = ::std::mem::transmute(bytes);
-            ::std::str::raw::from_utf8(bytes).slice_chars(0, 1)
+            Utf8Char{bytes: std::mem::transmute(buf)}
+        }
+    }
+
+    // None unless bytes is exactly one character.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Utf8Char>
+    {
+        match std::str::from_utf8(bytes)
+        {
+            Some(s) if s.char_len() == 1 => Some(Utf8Char::from_char(s.char_at(0))),
+            _ => None,
+        }
+    }
+
+    // The bytes up to the NUL that ends them.
+    pub fn as_bytes<'a>(&'a self) -> &'a [u8]
+    {
+        let bytes: &[c::c_char] = self.bytes;
+        let bytes: &[u8] = unsafe { ::std::mem::transmute(bytes) };
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        bytes.slice_to(len)
+    }
+
+    // None unless the bytes are one whole character; no bytes at all
+    // is U+0000, as libtermkey writes it.
+    pub fn to_char(&self) -> Option<char>
+    {
+        match std::str::from_utf8(self.as_bytes())
+        {
+            Some("") => Some('\0'),
+            Some(s) if s.char_len() == 1 => Some(s.char_at(0)),
+            _ => None,
+        }
+    }
+
+    // U+FFFD if the bytes aren't one whole character.
+    pub fn s<'a>(&'a self) -> &'a str
+    {
+        match std::str::from_utf8(self.as_bytes())
+        {
+            Some("") => "\0",
+            Some(s) if s.char_len() == 1 => s,
+            _ => "\ufffd",
         }
     }
 }
 
 // called TermKeyKey in C
-#[deriving(PartialEq, PartialOrd)]
+#[deriving(PartialEq, PartialOrd, Clone)]
 pub enum TermKeyEvent
 {
     UnknownCsiEvent,
@@ -210,7 +397,30 @@ pub enum TermKeyEvent
     KeySymEvent{pub sym: c::TermKeySym, pub mods: c::X_TermKey_KeyMod},
     MouseEvent{pub ev: c::TermKeyMouseEvent, pub mods: c::X_TermKey_KeyMod, pub button: int, pub line: int, pub col: int},
     PositionEvent{pub line: int, pub col: int},
-    ModeReportEvent{pub initial: int, pub mode: int, pub value: int},
+    ModeReportEvent{pub mode: mode::Mode, pub state: mode::ModeState},
+
+    // not in libtermkey; see input.rs
+    DeviceAttributesEvent{pub attrs: da::DeviceAttributes},
+    TerminalVersionEvent{pub version: String},
+    // e.g. ESC ] 11 ; rgb:0000/0000/0000 ST
+    OscEvent{pub number: int, pub payload: Vec<u8>},
+    // intermediates includes any private marker and the final byte,
+    // e.g. "$r" for ESC P 1 $ r 0 m ST
+    DcsEvent{pub params: Vec<int>, pub intermediates: String, pub payload: Vec<u8>},
+    // CSI 48 ; rows ; cols ; height ; width t, or from SIGWINCH
+    ResizeEvent{pub rows: int, pub cols: int, pub pixel_width: int, pub pixel_height: int},
+    // after suspend() or SIGCONT; the screen needs redrawing
+    ResumeEvent,
+    // everything between CSI 200~ and CSI 201~, which terminals only send
+    // with bracketed paste mode (?2004) enabled; a paste longer than the
+    // string limit comes in more than one
+    PasteEvent{pub data: Vec<u8>},
+    // bytes that aren't UTF-8, in UTF-8 mode; or a codepoint that isn't
+    // a character (e.g. a surrogate, from CSI u), as libtermkey encodes it
+    InvalidUtf8Event{pub bytes: Vec<u8>},
+    // with TERMKEY_FLAG_RAW, a byte from 0x80 up, which libtermkey would
+    // take for a codepoint; Alt if it came after ESC
+    ByteEvent{pub byte: u8, pub mods: c::X_TermKey_KeyMod},
 }
 
 impl TermKeyEvent
@@ -221,11 +431,14 @@ impl TermKeyEvent
         {
             c::TERMKEY_TYPE_UNICODE =>
             {
-                unsafe
+                let mods = unsafe { std::mem::transmute(key.modifiers) };
+                let codepoint = unsafe { key.codepoint() };
+                // utf8 is encoded again rather than trusted
+                let ch = if codepoint < 0 || codepoint > 0x10ffff { None } else { std::char::from_u32(codepoint as u32) };
+                match ch
                 {
-                    UnicodeEvent{mods: std::mem::transmute(key.modifiers),
-                            codepoint: std::char::from_u32(key.codepoint() as u32).unwrap(),
-                            utf8: Utf8Char{bytes: key.utf8}}
+                    Some(ch) => UnicodeEvent{mods: mods, codepoint: ch, utf8: Utf8Char::from_char(ch)},
+                    None => InvalidUtf8Event{bytes: Utf8Char{bytes: key.utf8}.as_bytes().to_vec()},
                 }
             }
             c::TERMKEY_TYPE_FUNCTION =>
@@ -255,7 +468,7 @@ impl TermKeyEvent
                     if c::termkey_interpret_mouse(tk, &key,
                             &mut ev, &mut button, &mut line, &mut col) != c::TERMKEY_RES_KEY
                     {
-                        panic!()
+                        return UnknownCsiEvent;
                     }
                     MouseEvent{mods: std::mem::transmute(key.modifiers), ev: ev, button: button as int,
                             line: line as int, col: col as int}
@@ -270,7 +483,7 @@ impl TermKeyEvent
                     if c::termkey_interpret_position(tk, &key,
                             &mut line, &mut col) != c::TERMKEY_RES_KEY
                     {
-                        panic!()
+                        return UnknownCsiEvent;
                     }
                     PositionEvent{line: line as int, col: col as int}
                 }
@@ -285,21 +498,77 @@ impl TermKeyEvent
                     if c::termkey_interpret_modereport(tk, &key,
                             &mut initial, &mut mode, &mut value) != c::TERMKEY_RES_KEY
                     {
-                        panic!()
+                        return UnknownCsiEvent;
+                    }
+                    match mode::Mode::from_c(initial, mode)
+                    {
+                        Some(mode) => ModeReportEvent{mode: mode, state: mode::ModeState::from_c(value)},
+                        None => UnknownCsiEvent,
                     }
-                    ModeReportEvent{initial: initial as int, mode: mode as int, value: value as int}
                 }
             }
             c::TERMKEY_TYPE_UNKNOWN_CSI =>
             {
                 // termkey 0.17 hard-codes size as 16. Oops!
                 // once termkey is fixed we should change this to a loop
+                let mut args: [c::c_long, ..16] = [0, ..16];
+                let mut nargs: c::size_t = 16;
+                let mut cmd: c::c_ulong = 0;
+                unsafe
+                {
+                    if c::termkey_interpret_csi(tk, &key,
+                            &mut args[0], &mut nargs, &mut cmd) != c::TERMKEY_RES_KEY
+                    {
+                        return UnknownCsiEvent;
+                    }
+                }
+                let args = args.slice_to(nargs as uint);
+
+                match resize::from_csi(cmd, args)
+                {
+                    Some(key) => return key,
+                    None => {}
+                }
 
-                // Removed, I have decided not to expose this API
-                UnknownCsiEvent
+                // The raw CSI is still not exposed, only the replies we know.
+                match da::DeviceAttributes::from_csi(cmd, args)
+                {
+                    Some(attrs) => DeviceAttributesEvent{attrs: attrs},
+                    None => UnknownCsiEvent,
+                }
             }
         }
     }
+
+    // body is everything between ESC P and ST
+    fn from_dcs(body: &[u8]) -> TermKeyEvent
+    {
+        let (params, intermediates, data) = input::split_dcs(body);
+        match intermediates.as_slice()
+        {
+            // XTVERSION
+            ">|" =>
+            {
+                TerminalVersionEvent{version: String::from_utf8_lossy(data).into_string()}
+            }
+            // DA3
+            "!|" =>
+            {
+                DeviceAttributesEvent{attrs: da::Tertiary{unit_id: String::from_utf8_lossy(data).into_string()}}
+            }
+            _ =>
+            {
+                DcsEvent{params: params, intermediates: intermediates, payload: data.to_vec()}
+            }
+        }
+    }
+
+    // body is everything between ESC ] and ST
+    fn from_osc(body: &[u8]) -> TermKeyEvent
+    {
+        let (number, payload) = input::split_osc(body);
+        OscEvent{number: number, payload: payload.to_vec()}
+    }
 }
 
 pub enum TermKeyResult
@@ -329,46 +598,353 @@ impl TermKey
 {
     pub fn getkey(&mut self) -> TermKeyResult
     {
-        let mut key: c::TermKeyKey = std::default::Default::default();
-        let res = unsafe
+        self.check_signals();
+        match self.queue.pop_front()
         {
-            c::termkey_getkey(self.tk, &mut key)
-        };
-        TermKeyResult::from_c(self.tk, key, res)
+            Some(key) => return Key(key),
+            None => {}
+        }
+        self.getkey_filtered(false)
     }
     pub fn getkey_force(&mut self) -> TermKeyResult
     {
-        let mut key: c::TermKeyKey = std::default::Default::default();
-        let res = unsafe
+        self.check_signals();
+        match self.queue.pop_front()
         {
-            c::termkey_getkey_force(self.tk, &mut key)
-        };
-        TermKeyResult::from_c(self.tk, key, res)
+            Some(key) => return Key(key),
+            None => {}
+        }
+        self.getkey_filtered(true)
     }
+    // same as termkey_waitkey, but reading through the input filter,
+    // and also waking up for caught signals
     pub fn waitkey(&mut self) -> TermKeyResult
+    {
+        let fd = unsafe { c::termkey_get_fd(self.tk) };
+        if fd == -1
+        {
+            if self.reader.is_some()
+            {
+                return self.waitkey_reader();
+            }
+            return Error{errno: libc::EBADF};
+        }
+        loop
+        {
+            self.check_signals();
+            match self.queue.pop_front()
+            {
+                Some(key) => return Key(key),
+                None => {}
+            }
+            let waittime = match self.getkey_filtered(false)
+            {
+                None_ => -1,
+                Again =>
+                {
+                    if !self.is_started()
+                    {
+                        return Error{errno: libc::EINVAL};
+                    }
+                    // the rest of a paste is worth waiting for
+                    if self.input.in_paste() { -1 } else { self.get_waittime() }
+                }
+                res => return res,
+            };
+            let wakeup = match self.signals
+            {
+                Some(ref signals) => signals.wakeup_fd(),
+                None => -1,
+            };
+            let (pollret, readable) = poll::poll_rd2(fd, wakeup, waittime);
+            if pollret < 0
+            {
+                let errno = std::os::errno() as c::c_int;
+                if errno == libc::EINTR && !self.get_flags().contains(c::TERMKEY_FLAG_EINTR)
+                {
+                    continue;
+                }
+                return Error{errno: errno};
+            }
+            if pollret == 0
+            {
+                // waittime is up, so whatever is there is all there is
+                return self.getkey_filtered(true);
+            }
+            if readable
+            {
+                match self.advisereadable()
+                {
+                    Error{errno} => return Error{errno: errno},
+                    // e.g. EOF in the middle of a key
+                    None_ if waittime >= 0 => return self.getkey_filtered(true),
+                    _ => {}
+                }
+            }
+        }
+    }
+    // With a reader but no fd, the reader is all there is to wait on.
+    fn waitkey_reader(&mut self) -> TermKeyResult
+    {
+        loop
+        {
+            self.check_signals();
+            match self.queue.pop_front()
+            {
+                Some(key) => return Key(key),
+                None => {}
+            }
+            let eof = self.eof;
+            match self.getkey_filtered(eof)
+            {
+                None_ | Again => {}
+                res => return res,
+            }
+            match self.advisereadable()
+            {
+                Error{errno} => return Error{errno: errno},
+                _ => {}
+            }
+        }
+    }
+    // will never return Key
+    pub fn advisereadable(&mut self) -> TermKeyResult
+    {
+        // as much as there is room for, as termkey_advisereadable does
+        let size = self.room();
+        if size == 0
+        {
+            return Error{errno: libc::ENOMEM};
+        }
+        if self.readbuf.len() < size
+        {
+            let more = size - self.readbuf.len();
+            self.readbuf.grow(more, 0u8);
+        }
+        if self.reader.is_some()
+        {
+            let res = self.reader.as_mut().unwrap().read(self.readbuf.slice_to_mut(size));
+            return match res
+            {
+                Ok(len) =>
+                {
+                    self.input.feed(self.readbuf.slice_to(len));
+                    self.feed_c();
+                    Again
+                }
+                Err(e) => match e.kind
+                {
+                    std::io::EndOfFile =>
+                    {
+                        self.eof = true;
+                        None_
+                    }
+                    std::io::ResourceUnavailable => None_,
+                    _ => Error{errno: libc::EIO},
+                },
+            };
+        }
+        let fd = unsafe { c::termkey_get_fd(self.tk) };
+        if fd == -1
+        {
+            return Error{errno: libc::EBADF};
+        }
+        loop
+        {
+            let len = unsafe
+            {
+                let p: *mut libc::c_void = std::mem::transmute(self.readbuf.as_mut_ptr());
+                libc::read(fd, p, size as libc::size_t)
+            };
+            if len < 0
+            {
+                let errno = std::os::errno() as c::c_int;
+                if errno == libc::EAGAIN
+                {
+                    return None_;
+                }
+                if errno == libc::EINTR && !self.get_flags().contains(c::TERMKEY_FLAG_EINTR)
+                {
+                    continue;
+                }
+                return Error{errno: errno};
+            }
+            if len == 0
+            {
+                self.eof = true;
+                return None_;
+            }
+            self.input.feed(self.readbuf.slice_to(len as uint));
+            self.feed_c();
+            return Again;
+        }
+    }
+
+    // Catch sig, and have waitkey wake up for it.
+    fn catch_signal(&mut self, sig: c::c_int)
+    {
+        if self.signals.is_none()
+        {
+            self.signals = Some(signal::Watcher::new());
+        }
+        signal::catch(sig);
+    }
+
+    // Has sig arrived since last time?
+    fn take_signal(&mut self, sig: c::c_int) -> bool
+    {
+        match self.signals
+        {
+            Some(ref mut signals) => signals.take(sig),
+            None => false,
+        }
+    }
+
+    // Queue events for any signals that arrived since last time.
+    fn check_signals(&mut self)
+    {
+        match self.signals
+        {
+            Some(ref mut signals) => signals.drain(),
+            None => return,
+        }
+        if self.winch && self.take_signal(signal::SIGWINCH)
+        {
+            let fd = unsafe { c::termkey_get_fd(self.tk) };
+            match resize::window_size(fd)
+            {
+                Some(key) => self.queue.push_back(key),
+                None => {}
+            }
+        }
+        if self.job_control
+        {
+            // both are taken at once: a SIGCONT from before the stop
+            // needs no resume() of its own, as suspend() does that
+            let tstp = self.take_signal(signal::SIGTSTP);
+            let cont = self.take_signal(signal::SIGCONT);
+            if tstp
+            {
+                self.suspend();
+            }
+            else if cont
+            {
+                self.resume();
+            }
+        }
+    }
+
+    // Give libtermkey as much of the filtered input as it has room for.
+    fn feed_c(&mut self)
+    {
+        loop
+        {
+            let room = self.get_buffer_remaining();
+            if room == 0
+            {
+                return;
+            }
+            match self.input.take_bytes(room)
+            {
+                Some(bytes) =>
+                {
+                    unsafe
+                    {
+                        c::termkey_push_bytes(self.tk, std::mem::transmute(&bytes[0]), bytes.len() as c::size_t);
+                    }
+                }
+                None => return,
+            }
+        }
+    }
+
+    // Events picked out by the filter come after whatever libtermkey
+    // already has, so they are only returned once its buffer is empty.
+    fn getkey_filtered(&mut self, force: bool) -> TermKeyResult
+    {
+        loop
+        {
+            self.feed_c();
+            let res = if force { self.getkey_force_c() } else { self.getkey_c() };
+            match res
+            {
+                None_ => {}
+                _ => return res,
+            }
+            match self.input.take_event()
+            {
+                Some(key) => return Key(self.decode_byte(key)),
+                None => {}
+            }
+            if !self.input.is_holding()
+            {
+                return if self.eof { Eof } else { None_ };
+            }
+            // A paste only ends at its end (or EOF), forced or not.
+            if self.input.in_paste()
+            {
+                if !self.eof
+                {
+                    return Again;
+                }
+            }
+            else if !force
+            {
+                return Again;
+            }
+            self.input.release();
+        }
+    }
+
+    // With a charset, a ByteEvent is the character it stands for.
+    fn decode_byte(&self, key: TermKeyEvent) -> TermKeyEvent
+    {
+        let ch = match (&key, &self.charset)
+        {
+            (&ByteEvent{byte, ..}, &Some(ref charset)) => charset.decode(byte),
+            _ => None,
+        };
+        match (ch, key)
+        {
+            (Some(ch), ByteEvent{mods, ..}) => keymap::unicode(ch, mods),
+            (_, key) => key,
+        }
+    }
+
+    fn getkey_c(&mut self) -> TermKeyResult
     {
         let mut key: c::TermKeyKey = std::default::Default::default();
         let res = unsafe
         {
-            c::termkey_waitkey(self.tk, &mut key)
+            c::termkey_getkey(self.tk, &mut key)
         };
         TermKeyResult::from_c(self.tk, key, res)
     }
-    // will never return Key
-    pub fn advisereadable(&mut self) -> TermKeyResult
+    fn getkey_force_c(&mut self) -> TermKeyResult
     {
+        let mut key: c::TermKeyKey = std::default::Default::default();
         let res = unsafe
         {
-            c::termkey_advisereadable(self.tk)
+            c::termkey_getkey_force(self.tk, &mut key)
         };
-        TermKeyResult::from_c(self.tk, std::default::Default::default(), res)
+        TermKeyResult::from_c(self.tk, key, res)
     }
+    // As termkey_push_bytes, takes only as much as there is room for,
+    // and returns how much that was; 0 means the buffer is full.
     pub fn push_bytes(&mut self, bytes: &[u8]) -> uint
     {
-        unsafe
-        {
-            c::termkey_push_bytes(self.tk, std::mem::transmute(&bytes[0]), bytes.len() as c::size_t) as uint
-        }
+        let len = std::cmp::min(bytes.len(), self.room());
+        self.input.feed(bytes.slice_to(len));
+        self.feed_c();
+        len
+    }
+
+    // libtermkey's free space, less what the filter has waiting for it
+    fn room(&mut self) -> uint
+    {
+        let remaining = self.get_buffer_remaining();
+        let waiting = self.input.waiting();
+        if waiting >= remaining { 0 } else { remaining - waiting }
     }
 }
 
@@ -390,27 +966,20 @@ impl TermKey
     {
         unsafe
         {
-            s.with_c_str(|cbuf|
+            c_prefix(s).with_c_str(|cbuf|
             {
                 let rbuf = c::termkey_lookup_keyname(self.tk, cbuf, sym);
-                let ci = cbuf as uint;
-                let ri = rbuf as uint;
-                if ri != 0
-                {
-                    let off = ri - ci;
-                    let sbytelen = s.as_bytes().len();
-                    Some(std::str::raw::slice_unchecked(s, off, sbytelen))
-                }
-                else
-                {
-                    None
-                }
+                c_rest(s, cbuf, rbuf)
             })
         }
     }
 
     pub fn keyname2sym(&mut self, keyname: &str) -> c::TermKeySym
     {
+        if keyname.contains_char('\0')
+        {
+            return c::TERMKEY_SYM_UNKNOWN;
+        }
         unsafe
         {
             keyname.with_c_str(|name|
@@ -423,10 +992,13 @@ impl TermKey
 
 impl TermKey
 {
-    pub fn strfkey(&mut self, key: TermKeyEvent, format: c::TermKeyFormat) -> String
+    // The key is borrowed, so that it can still be used afterwards. This
+    // is a change: strfkey used to take it by value, and callers that
+    // passed a TermKeyEvent now have to pass &key.
+    pub fn strfkey(&mut self, key: &TermKeyEvent, format: c::TermKeyFormat) -> String
     {
         let mut buf: [c::c_char, ..52] = [0, ..52];
-        let mut key_ = match key
+        let mut key_ = match *key
         {
             UnicodeEvent{mods, codepoint, utf8} =>
             {
@@ -448,46 +1020,406 @@ impl TermKey
             {
                 c::TermKeyKey::from_position(self.tk, line as c::c_int, col as c::c_int)
             }
-            ModeReportEvent{initial, mode, value} =>
+            ModeReportEvent{mode, state} =>
             {
-                c::TermKeyKey::from_mode_report(self.tk, initial as c::c_int, mode as c::c_int, value as c::c_int)
+                return format!("Mode({}={})", mode, state);
             }
             UnknownCsiEvent =>
             {
                 // TODO implement
                 return "unknown csi (stringification not implemented)".to_string();
             }
+            DeviceAttributesEvent{ref attrs} =>
+            {
+                return format!("{}", attrs);
+            }
+            TerminalVersionEvent{ref version} =>
+            {
+                return format!("Version({})", version);
+            }
+            OscEvent{number, ..} =>
+            {
+                return format!("Osc({})", number);
+            }
+            DcsEvent{ref intermediates, ..} =>
+            {
+                return format!("Dcs({})", intermediates);
+            }
+            ResizeEvent{rows, cols, ..} =>
+            {
+                return format!("Resize({}x{})", cols, rows);
+            }
+            ResumeEvent =>
+            {
+                return "Resume".to_string();
+            }
+            PasteEvent{ref data} =>
+            {
+                return format!("Paste({})", data.len());
+            }
+            InvalidUtf8Event{ref bytes} =>
+            {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", *b)).collect();
+                return wrap_bracket(format!("InvalidUtf8({})", hex.connect(" ")), format);
+            }
+            ByteEvent{byte, mods} =>
+            {
+                let prefix = self.strfmods(mods, format);
+                return wrap_bracket(format!("{}Byte({:02x})", prefix, byte), format);
+            }
         };
         unsafe
         {
+            // like snprintf, the size is what it would have written
             let sz = c::termkey_strfkey(self.tk, &mut buf[0], 52, &mut key_, format) as uint;
-            assert!(sz < 52, "key name should not be that long!");
-            std::str::raw::from_utf8(std::mem::transmute(buf.slice(0, sz))).to_string()
+            let sz = std::cmp::min(sz, 51);
+            let bytes: &[u8] = std::mem::transmute(buf.slice(0, sz));
+            String::from_utf8_lossy(bytes).into_string()
         }
     }
 
+    // The modifiers as strfkey writes them for a key of libtermkey's,
+    // e.g. "M-" with ALTISMETA, or "Ctrl-" with LONGMOD.
+    fn strfmods(&mut self, mods: c::X_TermKey_KeyMod, format: c::TermKeyFormat) -> String
+    {
+        let s = self.strfkey(&FunctionEvent{num: 1, mods: mods}, format - c::TERMKEY_FORMAT_WRAPBRACKET);
+        // less the "F1"
+        s.as_slice().slice_to(s.len() - 2).to_string()
+    }
+
+    // Byte(xx) and InvalidUtf8(xx ...), which libtermkey can't read
+    fn strpkey_bytes<'a>(&mut self, s: &'a str, format: c::TermKeyFormat) -> Option<(TermKeyEvent, &'a str)>
+    {
+        for &name in ["Byte(", "InvalidUtf8("].iter()
+        {
+            let start = match s.find_str(name)
+            {
+                Some(start) => start,
+                None => continue,
+            };
+            let end = match s.slice_from(start).find(')')
+            {
+                Some(i) => start + i,
+                None => return None,
+            };
+            let bytes: Option<Vec<u8>> = s.slice(start + name.len(), end).split(' ')
+                    .map(|hex| if hex.len() == 2 { std::num::from_str_radix(hex, 16) } else { None }).collect();
+            let bytes = match bytes
+            {
+                Some(bytes) => bytes,
+                None => return None,
+            };
+            // anything before the name has to be modifiers, as on F1
+            let prefix = s.slice_to(start);
+            let mods = if prefix.is_empty()
+            {
+                c::X_TermKey_KeyMod::empty()
+            }
+            else
+            {
+                let probe = format!("{}F1", prefix);
+                match self.strpkey_c(probe.as_slice(), format - c::TERMKEY_FORMAT_LOWERSPACE)
+                {
+                    Some((FunctionEvent{num: 1, mods}, "")) => mods,
+                    _ => return None,
+                }
+            };
+            let key = match (name, bytes.as_slice())
+            {
+                ("Byte(", [byte]) => ByteEvent{byte: byte, mods: mods},
+                ("InvalidUtf8(", _) if mods.is_empty() => InvalidUtf8Event{bytes: bytes.clone()},
+                _ => return None,
+            };
+            return Some((key, s.slice_from(end + 1)));
+        }
+        None
+    }
+
     pub fn strpkey<'a>(&mut self, s: &'a str, format: c::TermKeyFormat) -> Option<(TermKeyEvent, &'a str)>
+    {
+        // libtermkey's strfkey writes the brackets, but its strpkey
+        // doesn't read them; the key itself may be '>', as in <C->>
+        if format.contains(c::TERMKEY_FORMAT_WRAPBRACKET) && s.starts_with("<")
+        {
+            let inner = format - c::TERMKEY_FORMAT_WRAPBRACKET;
+            for (i, _) in s.char_indices().filter(|&(_, ch)| ch == '>')
+            {
+                match self.strpkey(s.slice(1, i), inner)
+                {
+                    Some((key, "")) => return Some((key, s.slice_from(i + 1))),
+                    _ => {}
+                }
+            }
+        }
+        match self.strpkey_bytes(s, format - c::TERMKEY_FORMAT_WRAPBRACKET)
+        {
+            Some(x) => return Some(x),
+            None => {}
+        }
+        let (key, rest) = match self.strpkey_c(s, format)
+        {
+            Some(x) => x,
+            None => return None,
+        };
+        if format.contains(c::TERMKEY_FORMAT_LOWERSPACE)
+        {
+            return Some(self.fix_lowerspace(s, key, rest));
+        }
+        Some((key, rest))
+    }
+
+    // With LOWERSPACE, libtermkey takes the first name that matches,
+    // so "delete" is DEL with "ete" left over; and it writes "f5", but
+    // only reads "F5", so that is f with "5" left over.
+    fn fix_lowerspace<'a>(&mut self, s: &'a str, key: TermKeyEvent, rest: &'a str) -> (TermKeyEvent, &'a str)
+    {
+        let (mods, len) = match key
+        {
+            UnicodeEvent{codepoint, mods, ..} => (mods, codepoint.len_utf8_bytes()),
+            KeySymEvent{sym, mods} => (mods, lower_spaced(self.get_keyname(sym)).len()),
+            _ => return (key, rest),
+        };
+        let end = s.len() - rest.len();
+        if len > end || !s.is_char_boundary(end - len)
+        {
+            return (key, rest);
+        }
+        let start = end - len;
+
+        // the longest name that starts where this key did
+        let mut best = None;
+        let mut best_len = len;
+        for sym in keymap::all_syms().into_iter()
+        {
+            let name = lower_spaced(self.get_keyname(sym));
+            if name.len() > best_len && s.slice_from(start).starts_with(name.as_slice())
+            {
+                best = Some(sym);
+                best_len = name.len();
+            }
+        }
+        match best
+        {
+            Some(c::TERMKEY_SYM_SPACE) => return (keymap::space(self, mods), s.slice_from(start + best_len)),
+            Some(sym) => return (KeySymEvent{sym: sym, mods: mods}, s.slice_from(start + best_len)),
+            None => {}
+        }
+
+        match key
+        {
+            UnicodeEvent{codepoint: 'f', mods, ..} =>
+            {
+                let digits = rest.chars().take_while(|&ch| ch >= '0' && ch <= '9').count();
+                match from_str::<int>(rest.slice_to(digits))
+                {
+                    Some(num) => return (FunctionEvent{num: num, mods: mods}, rest.slice_from(digits)),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+        (key, rest)
+    }
+
+    fn strpkey_c<'a>(&mut self, s: &'a str, format: c::TermKeyFormat) -> Option<(TermKeyEvent, &'a str)>
     {
         unsafe
         {
-            s.with_c_str(|cbuf|
+            c_prefix(s).with_c_str(|cbuf|
             {
                 let mut ckey : c::TermKeyKey = std::default::Default::default();
                 let rbuf = c::termkey_strpkey(self.tk, cbuf, &mut ckey, format);
-                let ci = cbuf as uint;
-                let ri = rbuf as uint;
-                if ri != 0
+                match c_rest(s, cbuf, rbuf)
+                {
+                    Some(rest) => Some((TermKeyEvent::from_c(self.tk, ckey), rest)),
+                    None => None,
+                }
+            })
+        }
+    }
+}
+
+// as libtermkey writes any key that isn't a plain character
+fn wrap_bracket(s: String, format: c::TermKeyFormat) -> String
+{
+    if format.contains(c::TERMKEY_FORMAT_WRAPBRACKET) { format!("<{}>", s) } else { s }
+}
+
+// "PageUp" as LOWERSPACE writes it: "page up"
+fn lower_spaced(name: &str) -> String
+{
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for ch in name.chars()
+    {
+        if ch.is_uppercase() && prev_lower
+        {
+            out.push(' ');
+        }
+        prev_lower = ch.is_lowercase();
+        out.push(ch.to_lowercase());
+    }
+    out
+}
+
+// C would stop at a NUL anyway, and with_c_str fails the task on one.
+fn c_prefix(s: &str) -> &str
+{
+    match s.find('\0')
+    {
+        Some(i) => s.slice_to(i),
+        None => s,
+    }
+}
+
+// The rest of s, after what C read from the copy at cbuf; None if C
+// returned NULL, or a pointer that isn't on a character boundary in s.
+fn c_rest<'a>(s: &'a str, cbuf: *const c::c_char, rbuf: *const c::c_char) -> Option<&'a str>
+{
+    let ci = cbuf as uint;
+    let ri = rbuf as uint;
+    if ri == 0 || ri < ci
+    {
+        return None;
+    }
+    let off = ri - ci;
+    if off > s.len() || !s.is_char_boundary(off)
+    {
+        return None;
+    }
+    Some(s.slice_from(off))
+}
+
+// Terminal queries.
+//
+// The reply to a query arrives in the same stream as keystrokes, so
+// any keys that are read while waiting are queued, and returned by the
+// following getkey/getkey_force/waitkey calls in the order they arrived.
+//
+// If there is no fd (new_abstract), the request is not written anywhere,
+// and the reply must be supplied with push_bytes.
+impl TermKey
+{
+    // DECXCPR, which unlike plain DSR 6 can't be confused with <F3>
+    pub fn query_cursor_position(&mut self, timeout_ms: int) -> Option<(int, int)>
+    {
+        self.write_request("\x1b[?6n".as_bytes());
+        self.wait_for_reply(timeout_ms, |key|
+        {
+            match *key
+            {
+                PositionEvent{line, col} => Some((line, col)),
+                _ => None,
+            }
+        })
+    }
+
+    // DECRQM for DEC private modes, RQM for ANSI modes
+    pub fn query_mode(&mut self, mode: mode::Mode, timeout_ms: int) -> Option<mode::ModeState>
+    {
+        let mode = mode.normalize();
+        let req = format!("\x1b[{}$p", mode.param());
+        self.write_request(req.as_bytes());
+        self.wait_for_reply(timeout_ms, |key|
+        {
+            match *key
+            {
+                ModeReportEvent{mode: m, state} if m == mode => Some(state),
+                _ => None,
+            }
+        })
+    }
+
+    fn write_request(&mut self, bytes: &[u8])
+    {
+        let fd = unsafe { c::termkey_get_fd(self.tk) };
+        if fd < 0
+        {
+            return;
+        }
+        let mut off = 0u;
+        while off < bytes.len()
+        {
+            let rv = unsafe
+            {
+                let p: *const libc::c_void = std::mem::transmute(&bytes[off]);
+                libc::write(fd, p, (bytes.len() - off) as libc::size_t)
+            };
+            if rv < 0
+            {
+                if std::os::errno() as c::c_int == libc::EINTR
                 {
-                    let key = TermKeyEvent::from_c(self.tk, ckey);
-                    let off = ri - ci;
-                    let sbytelen = s.as_bytes().len();
-                    Some((key, std::str::raw::slice_unchecked(s, off, sbytelen)))
+                    continue;
                 }
-                else
+                return;
+            }
+            off += rv as uint;
+        }
+    }
+
+    fn wait_for_reply<T>(&mut self, timeout_ms: int, matcher: |&TermKeyEvent| -> Option<T>) -> Option<T>
+    {
+        let deadline = deadline(timeout_ms);
+        let mut rv = None;
+        loop
+        {
+            match self.waitkey_until(deadline)
+            {
+                Some(key) =>
                 {
-                    None
+                    match matcher(&key)
+                    {
+                        Some(x) => { rv = Some(x); break; }
+                        None => { self.queue.push_back(key); }
+                    }
                 }
-            })
+                None => break,
+            }
+        }
+        rv
+    }
+
+    // like waitkey, but gives up at the deadline, and ignores the queue
+    fn waitkey_until(&mut self, deadline: u64) -> Option<TermKeyEvent>
+    {
+        let fd = unsafe { c::termkey_get_fd(self.tk) };
+        loop
+        {
+            let now = time::precise_time_ns();
+            let remaining = if now < deadline { ((deadline - now) / 1000000) as int } else { 0 };
+            match self.getkey_filtered(false)
+            {
+                Key(key) => return Some(key),
+                Again =>
+                {
+                    let waittime = self.get_waittime();
+                    if poll::poll_rd1(fd, waittime) > 0
+                    {
+                        self.advisereadable();
+                        continue;
+                    }
+                    match self.getkey_filtered(true)
+                    {
+                        Key(key) => return Some(key),
+                        _ if remaining == 0 => return None,
+                        _ => {}
+                    }
+                }
+                None_ =>
+                {
+                    if remaining == 0 || poll::poll_rd1(fd, remaining) <= 0
+                    {
+                        return None;
+                    }
+                    self.advisereadable();
+                }
+                _ => return None,
+            }
         }
     }
 }
+
+fn deadline(timeout_ms: int) -> u64
+{
+    time::precise_time_ns() + (timeout_ms as u64) * 1000000
+}
@@ -0,0 +1,918 @@
+// A line editor, for prompts: feed it keys from a TermKey and it edits
+// one line, drawing to any Writer.
+//
+// Both emacs (readline's defaults) and vi bindings are here, with a
+// kill ring, history and reverse-i-search (C-r). Pastes (with bracketed
+// paste mode enabled) are inserted as text, so a pasted newline doesn't
+// accept the line. The line is a single row; it is not wrapped.
+
+use std::io::{IoError, IoResult, Writer};
+
+use c;
+use {TermKey, TermKeyEvent, UnicodeEvent, KeySymEvent, PasteEvent, ResizeEvent, ResumeEvent};
+
+// the most kills that are kept
+static KILL_RING_SIZE: uint = 16;
+
+#[deriving(PartialEq, Clone, Show)]
+pub enum EditResult
+{
+    // the line is still being edited
+    Continue,
+    // Enter
+    Accept(String),
+    // C-c
+    Cancel,
+    // C-d on an empty line, or no more input
+    EndOfInput,
+}
+
+#[deriving(PartialEq, Clone, Show)]
+pub enum EditMode
+{
+    Emacs,
+    Vi,
+}
+
+// Called for Tab. Given the line and the (byte) position of the cursor,
+// returns where the word being completed starts, and the candidates.
+// The start is a byte offset too, on a char boundary, and not past the
+// cursor; a completion that breaks that is ignored.
+pub trait Completer
+{
+    fn complete(&mut self, line: &str, pos: uint) -> (uint, Vec<String>);
+}
+
+// what a key is, as far as the bindings care
+enum Press
+{
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Sym(c::TermKeySym, c::X_TermKey_KeyMod),
+    Unbound,
+}
+
+fn press(key: &TermKeyEvent) -> Press
+{
+    match *key
+    {
+        UnicodeEvent{codepoint, mods, ..} =>
+        {
+            if mods.contains(c::TERMKEY_KEYMOD_CTRL) { Ctrl(codepoint) }
+            else if mods.contains(c::TERMKEY_KEYMOD_ALT) { Alt(codepoint) }
+            else { Char(codepoint) }
+        }
+        KeySymEvent{sym: c::TERMKEY_SYM_SPACE, mods} if mods.is_empty() => Char(' '),
+        KeySymEvent{sym, mods} => Sym(sym, mods),
+        _ => Unbound,
+    }
+}
+
+// The last command, for the ones that depend on it.
+#[deriving(PartialEq)]
+enum Last
+{
+    Plain,
+    Kill,
+    // start, length, and kill ring index of what was yanked
+    Yank(uint, uint, uint),
+}
+
+struct Search
+{
+    query: String,
+    // the history entry found, if any
+    found: Option<uint>,
+    // the line as it was before the search
+    line: Vec<char>,
+    cursor: uint,
+}
+
+pub struct LineEditor<W>
+{
+    out: W,
+    prompt: String,
+    mode: EditMode,
+    line: Vec<char>,
+    // index into line
+    cursor: uint,
+    // vi command mode (rather than insert mode)
+    vi_command: bool,
+    // a vi operator (d or c) waiting for its motion
+    vi_operator: Option<char>,
+    kill_ring: Vec<String>,
+    last: Last,
+    history: Vec<String>,
+    // history.len() is the line being edited
+    history_pos: uint,
+    // the line being edited, while looking through the history
+    saved_line: Vec<char>,
+    search: Option<Search>,
+    completer: Option<Box<Completer + 'static>>,
+}
+
+impl<W: Writer> LineEditor<W>
+{
+    pub fn new(out: W, prompt: &str) -> LineEditor<W>
+    {
+        LineEditor{out: out, prompt: prompt.to_string(), mode: Emacs, line: Vec::new(), cursor: 0,
+                vi_command: false, vi_operator: None, kill_ring: Vec::new(), last: Plain,
+                history: Vec::new(), history_pos: 0, saved_line: Vec::new(), search: None,
+                completer: None}
+    }
+
+    pub fn get_prompt(&self) -> &str
+    {
+        self.prompt.as_slice()
+    }
+    pub fn set_prompt(&mut self, prompt: &str)
+    {
+        self.prompt = prompt.to_string();
+    }
+
+    pub fn get_mode(&self) -> EditMode
+    {
+        self.mode.clone()
+    }
+    // vi starts in insert mode
+    pub fn set_mode(&mut self, mode: EditMode)
+    {
+        self.mode = mode;
+        self.vi_command = false;
+        self.vi_operator = None;
+    }
+
+    pub fn set_completer(&mut self, completer: Box<Completer + 'static>)
+    {
+        self.completer = Some(completer);
+    }
+
+    // Empty lines, and repeats of the last line, are left out.
+    pub fn add_history(&mut self, line: &str)
+    {
+        if line.is_empty() || self.history.last().map_or(false, |last| last.as_slice() == line)
+        {
+            return;
+        }
+        self.history.push(line.to_string());
+        self.history_pos = self.history.len();
+    }
+    pub fn history(&self) -> &[String]
+    {
+        self.history.as_slice()
+    }
+
+    pub fn line(&self) -> String
+    {
+        self.line.iter().map(|&ch| ch).collect()
+    }
+    // in characters
+    pub fn cursor(&self) -> uint
+    {
+        self.cursor
+    }
+
+    pub fn writer(&mut self) -> &mut W
+    {
+        &mut self.out
+    }
+    pub fn into_writer(self) -> W
+    {
+        self.out
+    }
+
+    // A new, empty line, with the prompt drawn.
+    pub fn start(&mut self) -> IoResult<()>
+    {
+        self.reset();
+        self.redraw()
+    }
+
+    // Prompt and edit until the line is done with.
+    pub fn readline(&mut self, tk: &mut TermKey) -> IoResult<EditResult>
+    {
+        try!(self.start());
+        loop
+        {
+            match tk.waitkey()
+            {
+                ::Key(key) => match try!(self.handle_key(&key))
+                {
+                    Continue => {}
+                    res => return Ok(res),
+                },
+                ::Error{errno} => return Err(IoError::from_errno(errno as uint, false)),
+                ::Again => {}
+                ::Eof | ::None_ => return Ok(EndOfInput),
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: &TermKeyEvent) -> IoResult<EditResult>
+    {
+        match *key
+        {
+            PasteEvent{ref data} =>
+            {
+                self.search_done();
+                self.insert_paste(data.as_slice());
+                self.last = Plain;
+                try!(self.redraw());
+                return Ok(Continue);
+            }
+            ResizeEvent{..} | ResumeEvent =>
+            {
+                try!(self.redraw());
+                return Ok(Continue);
+            }
+            _ => {}
+        }
+
+        if self.search.is_some()
+        {
+            match try!(self.search_key(key))
+            {
+                Some(res) => return Ok(res),
+                None => {}
+            }
+        }
+
+        let last = ::std::mem::replace(&mut self.last, Plain);
+        let res = if self.mode == Vi && self.vi_command
+        {
+            self.vi_key(press(key), last)
+        }
+        else
+        {
+            self.emacs_key(press(key), last)
+        };
+        match res
+        {
+            Continue =>
+            {
+                try!(self.redraw());
+            }
+            Accept(_) =>
+            {
+                try!(self.redraw());
+                try!(self.out.write_str("\r\n"));
+                self.reset();
+            }
+            Cancel =>
+            {
+                try!(self.out.write_str("^C\r\n"));
+                self.reset();
+            }
+            EndOfInput =>
+            {
+                try!(self.out.write_str("\r\n"));
+                self.reset();
+            }
+        }
+        Ok(res)
+    }
+
+    fn reset(&mut self)
+    {
+        self.line.clear();
+        self.cursor = 0;
+        self.vi_command = false;
+        self.vi_operator = None;
+        self.last = Plain;
+        self.history_pos = self.history.len();
+        self.search = None;
+    }
+
+    fn redraw(&mut self) -> IoResult<()>
+    {
+        let mut s = String::from_str("\r");
+        match self.search
+        {
+            Some(ref search) => s.push_str(format!("(reverse-i-search)`{}': ", search.query).as_slice()),
+            None => s.push_str(self.prompt.as_slice()),
+        }
+        for &ch in self.line.iter()
+        {
+            s.push(ch);
+        }
+        s.push_str("\x1b[K");
+        let back = width(self.line.slice_from(self.cursor));
+        if back > 0
+        {
+            s.push_str(format!("\x1b[{}D", back).as_slice());
+        }
+        self.out.write_str(s.as_slice())
+    }
+
+    // Emacs bindings, and vi insert mode.
+    fn emacs_key(&mut self, p: Press, last: Last) -> EditResult
+    {
+        let vi = self.mode == Vi;
+        match p
+        {
+            Char(ch) => self.insert(ch),
+            Alt(ch) if vi =>
+            {
+                // ESC and a key, typed too fast to tell apart
+                self.vi_command = true;
+                self.cursor = self.prev(self.cursor);
+                return self.vi_key(Char(ch), last);
+            }
+            Sym(c::TERMKEY_SYM_ESCAPE, _) if vi =>
+            {
+                self.vi_command = true;
+                self.cursor = self.prev(self.cursor);
+            }
+            Sym(c::TERMKEY_SYM_ENTER, _) | Ctrl('m') | Ctrl('j') => return Accept(self.line()),
+            Ctrl('c') => return Cancel,
+            Ctrl('d') if self.line.is_empty() => return EndOfInput,
+            Ctrl('d') | Sym(c::TERMKEY_SYM_DELETE, _) =>
+            {
+                let (start, end) = (self.cursor, self.next(self.cursor));
+                self.remove(start, end);
+            }
+            Sym(c::TERMKEY_SYM_BACKSPACE, m) | Sym(c::TERMKEY_SYM_DEL, m) if m.is_empty() => self.backspace(),
+            Ctrl('h') => self.backspace(),
+            Ctrl('a') | Sym(c::TERMKEY_SYM_HOME, _) => self.cursor = 0,
+            Ctrl('e') | Sym(c::TERMKEY_SYM_END, _) => self.cursor = self.line.len(),
+            Sym(c::TERMKEY_SYM_LEFT, m) if m.is_empty() => self.cursor = self.prev(self.cursor),
+            Sym(c::TERMKEY_SYM_RIGHT, m) if m.is_empty() => self.cursor = self.next(self.cursor),
+            Ctrl('b') => self.cursor = self.prev(self.cursor),
+            Ctrl('f') => self.cursor = self.next(self.cursor),
+            Alt('b') | Sym(c::TERMKEY_SYM_LEFT, _) => self.cursor = self.word_back(self.cursor),
+            Alt('f') | Sym(c::TERMKEY_SYM_RIGHT, _) => self.cursor = self.word_forward(self.cursor),
+            Ctrl('k') =>
+            {
+                let (start, end) = (self.cursor, self.line.len());
+                self.kill(start, end, last);
+            }
+            Ctrl('u') =>
+            {
+                let end = self.cursor;
+                self.kill(0, end, last);
+            }
+            Ctrl('w') =>
+            {
+                // whitespace-delimited, as unix-word-rubout
+                let end = self.cursor;
+                let mut start = end;
+                while start > 0 && self.line[start - 1].is_whitespace() { start -= 1; }
+                while start > 0 && !self.line[start - 1].is_whitespace() { start -= 1; }
+                self.kill(start, end, last);
+            }
+            Alt('d') =>
+            {
+                let (start, end) = (self.cursor, self.word_forward(self.cursor));
+                self.kill(start, end, last);
+            }
+            Sym(c::TERMKEY_SYM_BACKSPACE, _) | Sym(c::TERMKEY_SYM_DEL, _) =>
+            {
+                let (start, end) = (self.word_back(self.cursor), self.cursor);
+                self.kill(start, end, last);
+            }
+            Ctrl('y') => self.yank(),
+            Alt('y') => self.yank_pop(last),
+            Ctrl('t') =>
+            {
+                if self.cursor > 0 && self.line.len() >= 2
+                {
+                    if self.cursor == self.line.len()
+                    {
+                        self.cursor -= 1;
+                    }
+                    let i = self.cursor;
+                    self.line.as_mut_slice().swap(i - 1, i);
+                    self.cursor += 1;
+                }
+            }
+            Ctrl('l') =>
+            {
+                // redrawn after
+                let _ = self.out.write_str("\x1b[H\x1b[2J");
+            }
+            Ctrl('p') | Sym(c::TERMKEY_SYM_UP, _) => self.history_prev(),
+            Ctrl('n') | Sym(c::TERMKEY_SYM_DOWN, _) => self.history_next(),
+            Ctrl('r') =>
+            {
+                self.search = Some(Search{query: String::new(), found: None,
+                        line: self.line.clone(), cursor: self.cursor});
+            }
+            Sym(c::TERMKEY_SYM_TAB, m) if m.is_empty() => self.complete(),
+            Ctrl('i') => self.complete(),
+            _ => {}
+        }
+        Continue
+    }
+
+    // vi command mode
+    fn vi_key(&mut self, p: Press, last: Last) -> EditResult
+    {
+        let ch = match p
+        {
+            Char(ch) => ch,
+            Sym(c::TERMKEY_SYM_LEFT, _) => 'h',
+            Sym(c::TERMKEY_SYM_RIGHT, _) => 'l',
+            Sym(c::TERMKEY_SYM_UP, _) => 'k',
+            Sym(c::TERMKEY_SYM_DOWN, _) => 'j',
+            Sym(c::TERMKEY_SYM_HOME, _) => '0',
+            Sym(c::TERMKEY_SYM_END, _) => '$',
+            Sym(c::TERMKEY_SYM_ESCAPE, _) =>
+            {
+                self.vi_operator = None;
+                return Continue;
+            }
+            _ =>
+            {
+                // Enter, C-c and the like work the same in both modes
+                self.vi_operator = None;
+                return self.emacs_key(p, last);
+            }
+        };
+
+        match self.vi_operator.take()
+        {
+            Some(op) =>
+            {
+                let (start, end) = if ch == op
+                {
+                    // dd, cc
+                    (0, self.line.len())
+                }
+                else
+                {
+                    let from = self.cursor;
+                    match self.vi_motion(ch)
+                    {
+                        Some(to) if to < from => (to, from),
+                        Some(to) => (from, to),
+                        None => return Continue,
+                    }
+                };
+                self.kill(start, end, Plain);
+                self.cursor = start;
+                if op == 'c'
+                {
+                    self.vi_command = false;
+                }
+                self.vi_clamp();
+                return Continue;
+            }
+            None => {}
+        }
+
+        match self.vi_motion(ch)
+        {
+            Some(to) =>
+            {
+                self.cursor = to;
+                self.vi_clamp();
+                return Continue;
+            }
+            None => {}
+        }
+        match ch
+        {
+            'd' | 'c' => self.vi_operator = Some(ch),
+            'i' => self.vi_command = false,
+            'a' =>
+            {
+                self.cursor = self.next(self.cursor);
+                self.vi_command = false;
+            }
+            'I' =>
+            {
+                self.cursor = 0;
+                self.vi_command = false;
+            }
+            'A' =>
+            {
+                self.cursor = self.line.len();
+                self.vi_command = false;
+            }
+            'x' =>
+            {
+                let (start, end) = (self.cursor, self.next(self.cursor));
+                self.kill(start, end, Plain);
+            }
+            'X' =>
+            {
+                let (start, end) = (self.prev(self.cursor), self.cursor);
+                self.kill(start, end, Plain);
+            }
+            'D' | 'C' =>
+            {
+                let (start, end) = (self.cursor, self.line.len());
+                self.kill(start, end, Plain);
+                self.vi_command = ch == 'D';
+            }
+            'p' =>
+            {
+                self.cursor = self.next(self.cursor);
+                self.yank();
+                self.cursor = self.prev(self.cursor);
+            }
+            'P' =>
+            {
+                self.yank();
+                self.cursor = self.prev(self.cursor);
+            }
+            'k' => self.history_prev(),
+            'j' => self.history_next(),
+            _ => {}
+        }
+        self.vi_clamp();
+        Continue
+    }
+
+    // where a vi motion goes, if ch is one
+    fn vi_motion(&self, ch: char) -> Option<uint>
+    {
+        match ch
+        {
+            'h' => Some(self.prev(self.cursor)),
+            'l' => Some(self.next(self.cursor)),
+            '0' | '^' => Some(0),
+            '$' => Some(self.line.len()),
+            'w' =>
+            {
+                let mut i = self.cursor;
+                while i < self.line.len() && is_word(self.line[i]) { i += 1; }
+                while i < self.line.len() && !is_word(self.line[i]) { i += 1; }
+                Some(i)
+            }
+            'b' => Some(self.word_back(self.cursor)),
+            'e' =>
+            {
+                let i = self.word_forward(self.next(self.cursor));
+                Some(if i > 0 { self.prev(i) } else { 0 })
+            }
+            _ => None,
+        }
+    }
+
+    // In command mode the cursor is on a character, not after the last.
+    fn vi_clamp(&mut self)
+    {
+        if self.vi_command && self.cursor >= self.line.len() && self.cursor > 0
+        {
+            self.cursor = self.prev(self.line.len());
+        }
+    }
+
+    // A key during C-r; None if it ended the search, to be handled as usual.
+    fn search_key(&mut self, key: &TermKeyEvent) -> IoResult<Option<EditResult>>
+    {
+        match press(key)
+        {
+            Char(ch) =>
+            {
+                self.search.as_mut().unwrap().query.push(ch);
+                let from = self.history.len();
+                self.search_from(from);
+            }
+            Ctrl('r') =>
+            {
+                let from = self.search.as_ref().unwrap().found.unwrap_or(self.history.len());
+                self.search_from(from);
+            }
+            Sym(c::TERMKEY_SYM_BACKSPACE, _) | Sym(c::TERMKEY_SYM_DEL, _) | Ctrl('h') =>
+            {
+                self.search.as_mut().unwrap().query.pop();
+                let from = self.history.len();
+                self.search_from(from);
+            }
+            Ctrl('g') =>
+            {
+                let search = self.search.take().unwrap();
+                self.line = search.line;
+                self.cursor = search.cursor;
+            }
+            _ =>
+            {
+                self.search_done();
+                return Ok(None);
+            }
+        }
+        try!(self.redraw());
+        Ok(Some(Continue))
+    }
+
+    // look for the query in entries older than from
+    fn search_from(&mut self, from: uint)
+    {
+        let query = self.search.as_ref().unwrap().query.clone();
+        if query.is_empty()
+        {
+            self.search.as_mut().unwrap().found = None;
+            return;
+        }
+        let mut i = from;
+        while i > 0
+        {
+            i -= 1;
+            if self.history[i].as_slice().contains(query.as_slice())
+            {
+                self.search.as_mut().unwrap().found = Some(i);
+                self.line = self.history[i].as_slice().chars().collect();
+                self.cursor = self.line.len();
+                return;
+            }
+        }
+    }
+
+    // keep whatever the search found
+    fn search_done(&mut self)
+    {
+        match self.search.take()
+        {
+            Some(Search{found: Some(i), ..}) => self.history_pos = i,
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, ch: char)
+    {
+        let i = self.cursor;
+        self.line.insert(i, ch);
+        self.cursor += 1;
+    }
+
+    fn insert_str(&mut self, s: &str)
+    {
+        for ch in s.chars()
+        {
+            self.insert(ch);
+        }
+    }
+
+    // Line breaks and tabs become spaces, and other control characters
+    // are dropped, so a paste is only ever text on the line.
+    fn insert_paste(&mut self, data: &[u8])
+    {
+        let text = String::from_utf8_lossy(data).into_string();
+        let mut after_cr = false;
+        for ch in text.as_slice().chars()
+        {
+            match ch
+            {
+                '\n' if after_cr => {}
+                '\r' | '\n' | '\t' => self.insert(' '),
+                _ if ch < ' ' || ch == '\x7f' => {}
+                _ => self.insert(ch),
+            }
+            after_cr = ch == '\r';
+        }
+    }
+
+    fn remove(&mut self, start: uint, end: uint) -> String
+    {
+        let removed: String = self.line.slice(start, end).iter().map(|&ch| ch).collect();
+        let rest = self.line.slice_from(end).to_vec();
+        self.line.truncate(start);
+        self.line.push_all(rest.as_slice());
+        if self.cursor > end
+        {
+            self.cursor -= end - start;
+        }
+        else if self.cursor > start
+        {
+            self.cursor = start;
+        }
+        removed
+    }
+
+    fn backspace(&mut self)
+    {
+        let (start, end) = (self.prev(self.cursor), self.cursor);
+        self.remove(start, end);
+    }
+
+    // Kills in a row go together, as one entry.
+    fn kill(&mut self, start: uint, end: uint, last: Last)
+    {
+        if start >= end
+        {
+            self.last = last;
+            return;
+        }
+        let backward = end <= self.cursor;
+        let text = self.remove(start, end);
+        if last == Kill && !self.kill_ring.is_empty()
+        {
+            let top = self.kill_ring.pop().unwrap();
+            // killing backwards goes in front
+            let joined = if backward { text + top.as_slice() } else { top + text.as_slice() };
+            self.kill_ring.push(joined);
+        }
+        else
+        {
+            if self.kill_ring.len() == KILL_RING_SIZE
+            {
+                self.kill_ring.remove(0);
+            }
+            self.kill_ring.push(text);
+        }
+        self.last = Kill;
+    }
+
+    fn yank(&mut self)
+    {
+        if self.kill_ring.is_empty()
+        {
+            return;
+        }
+        let index = self.kill_ring.len() - 1;
+        self.yank_index(index);
+    }
+
+    // only straight after a yank: swap it for the kill before
+    fn yank_pop(&mut self, last: Last)
+    {
+        match last
+        {
+            Yank(start, len, index) =>
+            {
+                self.remove(start, start + len);
+                self.cursor = start;
+                let index = if index == 0 { self.kill_ring.len() - 1 } else { index - 1 };
+                self.yank_index(index);
+            }
+            _ => {}
+        }
+    }
+
+    fn yank_index(&mut self, index: uint)
+    {
+        let text = self.kill_ring[index].clone();
+        let start = self.cursor;
+        self.insert_str(text.as_slice());
+        self.last = Yank(start, self.cursor - start, index);
+    }
+
+    fn history_prev(&mut self)
+    {
+        if self.history_pos == 0
+        {
+            return;
+        }
+        if self.history_pos == self.history.len()
+        {
+            self.saved_line = self.line.clone();
+        }
+        self.history_pos -= 1;
+        self.line = self.history[self.history_pos].as_slice().chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    fn history_next(&mut self)
+    {
+        if self.history_pos >= self.history.len()
+        {
+            return;
+        }
+        self.history_pos += 1;
+        self.line = if self.history_pos == self.history.len()
+        {
+            self.saved_line.clone()
+        }
+        else
+        {
+            self.history[self.history_pos].as_slice().chars().collect()
+        };
+        self.cursor = self.line.len();
+    }
+
+    // The longest common prefix of the candidates replaces the word;
+    // if that doesn't change anything, they are listed instead.
+    fn complete(&mut self)
+    {
+        let line = self.line();
+        let pos = self.line.slice_to(self.cursor).iter().map(|ch| ch.len_utf8_bytes()).sum();
+        let (start, candidates) = match self.completer
+        {
+            Some(ref mut completer) => completer.complete(line.as_slice(), pos),
+            None => return,
+        };
+        if candidates.is_empty() || start > pos || !line.as_slice().is_char_boundary(start)
+        {
+            return;
+        }
+        let mut prefix = candidates[0].clone();
+        for cand in candidates.iter().skip(1)
+        {
+            let common = prefix.as_slice().chars().zip(cand.as_slice().chars())
+                    .take_while(|&(a, b)| a == b).map(|(a, _)| a.len_utf8_bytes()).sum();
+            prefix.truncate(common);
+        }
+        if prefix.as_slice() == line.as_slice().slice(start, pos) && candidates.len() > 1
+        {
+            let list = candidates.connect("  ");
+            let _ = self.out.write_str(format!("\r\n{}\r\n", list).as_slice());
+            return;
+        }
+        let first = line.as_slice().slice_to(start).char_len();
+        let word_end = line.as_slice().slice_to(pos).char_len();
+        self.remove(first, word_end);
+        self.cursor = first;
+        self.insert_str(prefix.as_slice());
+        if candidates.len() == 1
+        {
+            self.insert(' ');
+        }
+    }
+
+    // Over a whole character: any zero-width (combining) ones go with
+    // the one before.
+    fn prev(&self, pos: uint) -> uint
+    {
+        let mut i = pos;
+        while i > 0
+        {
+            i -= 1;
+            if char_width(self.line[i]) != 0
+            {
+                break;
+            }
+        }
+        i
+    }
+    fn next(&self, pos: uint) -> uint
+    {
+        if pos >= self.line.len()
+        {
+            return self.line.len();
+        }
+        let mut i = pos + 1;
+        while i < self.line.len() && char_width(self.line[i]) == 0
+        {
+            i += 1;
+        }
+        i
+    }
+
+    // emacs words: runs of letters and digits
+    fn word_forward(&self, pos: uint) -> uint
+    {
+        let mut i = pos;
+        while i < self.line.len() && !is_word(self.line[i]) { i += 1; }
+        while i < self.line.len() && is_word(self.line[i]) { i += 1; }
+        i
+    }
+    fn word_back(&self, pos: uint) -> uint
+    {
+        let mut i = pos;
+        while i > 0 && !is_word(self.line[i - 1]) { i -= 1; }
+        while i > 0 && is_word(self.line[i - 1]) { i -= 1; }
+        i
+    }
+}
+
+fn is_word(ch: char) -> bool
+{
+    ch.is_alphanumeric() || ch == '_'
+}
+
+// characters that take no column of their own
+static ZERO_WIDTH: &'static [(u32, u32)] = &[
+    (0x0300, 0x036f), (0x0483, 0x0489), (0x0591, 0x05bd), (0x0610, 0x061a),
+    (0x064b, 0x065f), (0x0e31, 0x0e31), (0x0e34, 0x0e3a), (0x0e47, 0x0e4e),
+    (0x1ab0, 0x1aff), (0x1dc0, 0x1dff), (0x200b, 0x200f), (0x20d0, 0x20ff),
+    (0xfe00, 0xfe0f), (0xfe20, 0xfe2f),
+];
+
+// East Asian wide and fullwidth characters, and emoji
+static WIDE: &'static [(u32, u32)] = &[
+    (0x1100, 0x115f), (0x2e80, 0x303e), (0x3041, 0x33ff), (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff), (0xa000, 0xa4cf), (0xac00, 0xd7a3), (0xf900, 0xfaff),
+    (0xfe30, 0xfe4f), (0xff00, 0xff60), (0xffe0, 0xffe6), (0x1f300, 0x1f64f),
+    (0x1f900, 0x1f9ff), (0x20000, 0x2fffd), (0x30000, 0x3fffd),
+];
+
+fn in_table(ch: char, table: &[(u32, u32)]) -> bool
+{
+    let cp = ch as u32;
+    table.iter().any(|&(lo, hi)| cp >= lo && cp <= hi)
+}
+
+// Columns taken on the terminal, as wcwidth.
+pub fn char_width(ch: char) -> uint
+{
+    if in_table(ch, ZERO_WIDTH)
+    {
+        0
+    }
+    else if in_table(ch, WIDE)
+    {
+        2
+    }
+    else
+    {
+        1
+    }
+}
+
+pub fn width(chars: &[char]) -> uint
+{
+    chars.iter().map(|&ch| char_width(ch)).sum()
+}
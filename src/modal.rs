@@ -0,0 +1,338 @@
+// Modal key dispatch, as in vi: a stack of modes, each with its own
+// keymap, with count prefixes (3w), operators that wait for a motion
+// (d in dw), and unbound characters inserted as text in modes that want
+// that. The application's own type A is what the keymaps bind to.
+//
+// Nothing here waits for time to pass; when a key is both bound and the
+// start of a longer binding, it stays pending until flush() is called.
+
+use c;
+use {TermKeyEvent, UnicodeEvent};
+
+#[deriving(PartialEq, Clone, Show)]
+pub enum Command<A>
+{
+    // a plain command, or a motion if an operator is pending
+    Action(A),
+    // waits for a motion, or for itself again (dd)
+    Operator(A),
+    // push the mode with this name
+    PushMode(String),
+    // back to the mode before
+    PopMode,
+}
+
+#[deriving(PartialEq, Clone, Show)]
+pub enum Dispatch<A>
+{
+    // the key (or count) is part of something longer
+    Pending,
+    // count is 1 if none was given
+    Run{pub action: A, pub count: uint},
+    // e.g. 3dw; for dd the motion is the operator
+    Apply{pub operator: A, pub motion: A, pub count: uint},
+    // an unbound character, in a mode that inserts them
+    Insert(char),
+    // the keys so far match no binding
+    Unbound(Vec<TermKeyEvent>),
+    // the mode with this name is now on top
+    ModeChanged(String),
+}
+
+pub struct Keymap<A>
+{
+    name: String,
+    bindings: Vec<(Vec<TermKeyEvent>, Command<A>)>,
+    insert: bool,
+    counts: bool,
+}
+
+impl<A: Clone> Keymap<A>
+{
+    // A keymap with no bindings, that neither inserts nor takes counts.
+    pub fn new(name: &str) -> Keymap<A>
+    {
+        Keymap{name: name.to_string(), bindings: Vec::new(), insert: false, counts: false}
+    }
+
+    pub fn get_name(&self) -> &str
+    {
+        self.name.as_slice()
+    }
+
+    // Replaces any binding for the same keys.
+    pub fn bind(&mut self, keys: &[TermKeyEvent], command: Command<A>)
+    {
+        if keys.is_empty()
+        {
+            return;
+        }
+        self.bindings.retain(|&(ref k, _)| k.as_slice() != keys);
+        self.bindings.push((keys.to_vec(), command));
+    }
+    pub fn unbind(&mut self, keys: &[TermKeyEvent])
+    {
+        self.bindings.retain(|&(ref k, _)| k.as_slice() != keys);
+    }
+
+    // Unbound characters are inserted, as in vi's insert mode.
+    pub fn set_insert(&mut self, insert: bool)
+    {
+        self.insert = insert;
+    }
+    // Digits before a command are a count, as in vi's normal mode.
+    // A leading 0 is not, so that it can be bound.
+    pub fn set_counts(&mut self, counts: bool)
+    {
+        self.counts = counts;
+    }
+
+    // (the command bound to exactly keys, whether a longer binding starts with them)
+    fn lookup(&self, keys: &[TermKeyEvent]) -> (Option<Command<A>>, bool)
+    {
+        let mut exact = None;
+        let mut longer = false;
+        for &(ref k, ref command) in self.bindings.iter()
+        {
+            if k.as_slice() == keys
+            {
+                exact = Some(command.clone());
+            }
+            else if k.len() > keys.len() && k.as_slice().starts_with(keys)
+            {
+                longer = true;
+            }
+        }
+        (exact, longer)
+    }
+}
+
+pub struct Dispatcher<A>
+{
+    modes: Vec<Keymap<A>>,
+    // indices into modes; never empty
+    stack: Vec<uint>,
+    keys: Vec<TermKeyEvent>,
+    count: Option<uint>,
+    // operator, its count, and the keys that gave it
+    operator: Option<(A, uint, Vec<TermKeyEvent>)>,
+}
+
+impl<A: Clone> Dispatcher<A>
+{
+    // base is the bottom of the stack, and can't be popped.
+    pub fn new(base: Keymap<A>) -> Dispatcher<A>
+    {
+        Dispatcher{modes: vec![base], stack: vec![0], keys: Vec::new(), count: None, operator: None}
+    }
+
+    // Replaces any mode with the same name.
+    pub fn add_mode(&mut self, keymap: Keymap<A>)
+    {
+        match self.find(keymap.get_name())
+        {
+            Some(i) => { self.modes.as_mut_slice()[i] = keymap; }
+            None => self.modes.push(keymap),
+        }
+    }
+
+    pub fn keymap_mut(&mut self, name: &str) -> Option<&mut Keymap<A>>
+    {
+        match self.find(name)
+        {
+            Some(i) => Some(&mut self.modes.as_mut_slice()[i]),
+            None => None,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<uint>
+    {
+        self.modes.iter().position(|m| m.get_name() == name)
+    }
+
+    // The name of the mode on top.
+    pub fn mode(&self) -> &str
+    {
+        self.modes[*self.stack.last().unwrap()].get_name()
+    }
+
+    pub fn push_mode(&mut self, name: &str) -> bool
+    {
+        match self.find(name)
+        {
+            Some(i) =>
+            {
+                self.stack.push(i);
+                self.reset();
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn pop_mode(&mut self) -> bool
+    {
+        if self.stack.len() == 1
+        {
+            return false;
+        }
+        self.stack.pop();
+        self.reset();
+        true
+    }
+
+    // Forget any pending keys, count and operator.
+    pub fn reset(&mut self)
+    {
+        self.keys.clear();
+        self.count = None;
+        self.operator = None;
+    }
+
+    pub fn pending_keys(&self) -> &[TermKeyEvent]
+    {
+        self.keys.as_slice()
+    }
+    pub fn pending_count(&self) -> Option<uint>
+    {
+        self.count
+    }
+    pub fn operator_pending(&self) -> bool
+    {
+        self.operator.is_some()
+    }
+
+    pub fn dispatch(&mut self, key: &TermKeyEvent) -> Dispatch<A>
+    {
+        let top = *self.stack.last().unwrap();
+        if self.keys.is_empty() && self.modes[top].counts
+        {
+            match digit(key)
+            {
+                Some(d) if d != 0 || self.count.is_some() =>
+                {
+                    self.count = Some(self.count.unwrap_or(0) * 10 + d);
+                    return Pending;
+                }
+                _ => {}
+            }
+        }
+
+        self.keys.push(key.clone());
+        let (exact, longer) = self.modes[top].lookup(self.keys.as_slice());
+        if longer
+        {
+            return Pending;
+        }
+        match exact
+        {
+            Some(command) => self.run(command),
+            None => self.unbound(top),
+        }
+    }
+
+    // Nothing more is coming for now (e.g. a timeout): take the pending
+    // keys as they are.
+    pub fn flush(&mut self) -> Dispatch<A>
+    {
+        if self.keys.is_empty()
+        {
+            return Pending;
+        }
+        let top = *self.stack.last().unwrap();
+        match self.modes[top].lookup(self.keys.as_slice())
+        {
+            (Some(command), _) => self.run(command),
+            (None, _) => self.unbound(top),
+        }
+    }
+
+    fn run(&mut self, command: Command<A>) -> Dispatch<A>
+    {
+        let keys = ::std::mem::replace(&mut self.keys, Vec::new());
+        let count = self.count.take().unwrap_or(1);
+        match command
+        {
+            Action(action) => match self.operator.take()
+            {
+                Some((operator, op_count, _)) =>
+                {
+                    Apply{operator: operator, motion: action, count: op_count * count}
+                }
+                None => Run{action: action, count: count},
+            },
+            Operator(operator) => match self.operator.take()
+            {
+                // dd
+                Some((_, op_count, ref op_keys)) if *op_keys == keys =>
+                {
+                    Apply{operator: operator.clone(), motion: operator, count: op_count * count}
+                }
+                Some((_, _, mut op_keys)) =>
+                {
+                    op_keys.push_all(keys.as_slice());
+                    Unbound(op_keys)
+                }
+                None =>
+                {
+                    self.operator = Some((operator, count, keys));
+                    Pending
+                }
+            },
+            PushMode(name) =>
+            {
+                if self.push_mode(name.as_slice())
+                {
+                    ModeChanged(name)
+                }
+                else
+                {
+                    self.reset();
+                    Unbound(keys)
+                }
+            }
+            PopMode =>
+            {
+                self.pop_mode();
+                self.reset();
+                ModeChanged(self.mode().to_string())
+            }
+        }
+    }
+
+    fn unbound(&mut self, top: uint) -> Dispatch<A>
+    {
+        let mut keys = ::std::mem::replace(&mut self.keys, Vec::new());
+        let count = self.count.take();
+        let operator = self.operator.take();
+        if keys.len() == 1 && count.is_none() && operator.is_none() && self.modes[top].insert
+        {
+            match keys[0]
+            {
+                UnicodeEvent{codepoint, mods, ..} if (mods - c::TERMKEY_KEYMOD_SHIFT).is_empty() =>
+                {
+                    return Insert(codepoint);
+                }
+                _ => {}
+            }
+        }
+        match operator
+        {
+            Some((_, _, mut op_keys)) =>
+            {
+                op_keys.push_all(keys.as_slice());
+                keys = op_keys;
+            }
+            None => {}
+        }
+        Unbound(keys)
+    }
+}
+
+fn digit(key: &TermKeyEvent) -> Option<uint>
+{
+    match *key
+    {
+        UnicodeEvent{codepoint, mods, ..} if mods.is_empty() => codepoint.to_digit(10),
+        _ => None,
+    }
+}
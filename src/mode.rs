@@ -0,0 +1,306 @@
+// Typed terminal modes, as seen in DECRQM/RQM replies (ModeReportEvent),
+// and set with enable_mode/disable_mode.
+
+use c;
+use TermKey;
+
+#[deriving(PartialEq, PartialOrd, Clone)]
+pub enum Mode
+{
+    /* ANSI modes, set with CSI Pm h */
+    KeyboardAction,         // KAM, 2
+    Insert,                 // IRM, 4
+    SendReceive,            // SRM, 12
+    LineFeedNewLine,        // LNM, 20
+
+    /* DEC private modes, set with CSI ? Pm h */
+    CursorKeys,             // DECCKM, 1
+    Column132,              // DECCOLM, 3
+    SmoothScroll,           // DECSCLM, 4
+    ReverseVideo,           // DECSCNM, 5
+    Origin,                 // DECOM, 6
+    AutoWrap,               // DECAWM, 7
+    AutoRepeat,             // DECARM, 8
+    X10Mouse,               // 9
+    BlinkingCursor,         // 12
+    ShowCursor,             // DECTCEM, 25
+    AltScreen,              // 47
+    ApplicationKeypad,      // DECNKM, 66
+    VT200Mouse,             // 1000
+    ButtonEventMouse,       // 1002
+    AnyEventMouse,          // 1003
+    FocusEvents,            // 1004
+    Utf8Mouse,              // 1005
+    SgrMouse,               // 1006
+    UrxvtMouse,             // 1015
+    AltScreenSaveCursor,    // 1049
+    BracketedPaste,         // 2004
+    SynchronizedOutput,     // 2026
+    InBandResize,           // 2048
+
+    /* anything else; Mode::new, dec and ansi only give these for modes
+       without a name above, and normalize turns the others back */
+    Other(u16),
+    OtherDec(u16),
+}
+
+impl Mode
+{
+    pub fn new(dec: bool, num: u16) -> Mode
+    {
+        if dec
+        {
+            match num
+            {
+                1 => CursorKeys,
+                3 => Column132,
+                4 => SmoothScroll,
+                5 => ReverseVideo,
+                6 => Origin,
+                7 => AutoWrap,
+                8 => AutoRepeat,
+                9 => X10Mouse,
+                12 => BlinkingCursor,
+                25 => ShowCursor,
+                47 => AltScreen,
+                66 => ApplicationKeypad,
+                1000 => VT200Mouse,
+                1002 => ButtonEventMouse,
+                1003 => AnyEventMouse,
+                1004 => FocusEvents,
+                1005 => Utf8Mouse,
+                1006 => SgrMouse,
+                1015 => UrxvtMouse,
+                1049 => AltScreenSaveCursor,
+                2004 => BracketedPaste,
+                2026 => SynchronizedOutput,
+                2048 => InBandResize,
+                _ => OtherDec(num),
+            }
+        }
+        else
+        {
+            match num
+            {
+                2 => KeyboardAction,
+                4 => Insert,
+                12 => SendReceive,
+                20 => LineFeedNewLine,
+                _ => Other(num),
+            }
+        }
+    }
+
+    // a DEC private mode, e.g. dec(1) is CursorKeys
+    pub fn dec(num: u16) -> Mode
+    {
+        Mode::new(true, num)
+    }
+
+    // an ANSI mode, e.g. ansi(4) is Insert
+    pub fn ansi(num: u16) -> Mode
+    {
+        Mode::new(false, num)
+    }
+
+    // the named mode for an Other/OtherDec that has one, so that
+    // OtherDec(1) compares equal to CursorKeys
+    pub fn normalize(&self) -> Mode
+    {
+        Mode::new(self.is_dec(), self.number())
+    }
+
+    // termkey_interpret_modereport gives the initial byte, '?' or 0;
+    // None for a mode number that doesn't fit
+    pub fn from_c(initial: c::c_int, mode: c::c_int) -> Option<Mode>
+    {
+        if mode < 0 || mode > 0xffff
+        {
+            return None;
+        }
+        let mode = mode as u16;
+        Some(if initial != 0 { Mode::dec(mode) } else { Mode::ansi(mode) })
+    }
+
+    pub fn is_dec(&self) -> bool
+    {
+        match *self
+        {
+            KeyboardAction | Insert | SendReceive | LineFeedNewLine | Other(_) => false,
+            _ => true,
+        }
+    }
+
+    pub fn number(&self) -> u16
+    {
+        match *self
+        {
+            KeyboardAction => 2,
+            Insert => 4,
+            SendReceive => 12,
+            LineFeedNewLine => 20,
+
+            CursorKeys => 1,
+            Column132 => 3,
+            SmoothScroll => 4,
+            ReverseVideo => 5,
+            Origin => 6,
+            AutoWrap => 7,
+            AutoRepeat => 8,
+            X10Mouse => 9,
+            BlinkingCursor => 12,
+            ShowCursor => 25,
+            AltScreen => 47,
+            ApplicationKeypad => 66,
+            VT200Mouse => 1000,
+            ButtonEventMouse => 1002,
+            AnyEventMouse => 1003,
+            FocusEvents => 1004,
+            Utf8Mouse => 1005,
+            SgrMouse => 1006,
+            UrxvtMouse => 1015,
+            AltScreenSaveCursor => 1049,
+            BracketedPaste => 2004,
+            SynchronizedOutput => 2026,
+            InBandResize => 2048,
+
+            Other(num) => num,
+            OtherDec(num) => num,
+        }
+    }
+
+    // the mnemonic from the DEC manuals, if there is one
+    pub fn name(&self) -> Option<&'static str>
+    {
+        match *self
+        {
+            KeyboardAction => Some("KAM"),
+            Insert => Some("IRM"),
+            SendReceive => Some("SRM"),
+            LineFeedNewLine => Some("LNM"),
+            CursorKeys => Some("DECCKM"),
+            Column132 => Some("DECCOLM"),
+            SmoothScroll => Some("DECSCLM"),
+            ReverseVideo => Some("DECSCNM"),
+            Origin => Some("DECOM"),
+            AutoWrap => Some("DECAWM"),
+            AutoRepeat => Some("DECARM"),
+            ShowCursor => Some("DECTCEM"),
+            ApplicationKeypad => Some("DECNKM"),
+            _ => None,
+        }
+    }
+
+    // the parameter as it appears in a CSI sequence, e.g. "?1049"
+    pub fn param(&self) -> String
+    {
+        if self.is_dec()
+        {
+            format!("?{}", self.number())
+        }
+        else
+        {
+            format!("{}", self.number())
+        }
+    }
+}
+
+impl ::std::fmt::Show for Mode
+{
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        match self.name()
+        {
+            Some(name) => write!(fmt, "{}", name),
+            None => write!(fmt, "{}", self.param()),
+        }
+    }
+}
+
+#[deriving(PartialEq, PartialOrd, Clone)]
+pub enum ModeState
+{
+    NotRecognized,
+    Set,
+    Reset,
+    PermanentlySet,
+    PermanentlyReset,
+}
+
+impl ModeState
+{
+    pub fn from_c(value: c::c_int) -> ModeState
+    {
+        match value
+        {
+            1 => Set,
+            2 => Reset,
+            3 => PermanentlySet,
+            4 => PermanentlyReset,
+            _ => NotRecognized,
+        }
+    }
+
+    pub fn to_c(&self) -> c::c_int
+    {
+        match *self
+        {
+            NotRecognized => 0,
+            Set => 1,
+            Reset => 2,
+            PermanentlySet => 3,
+            PermanentlyReset => 4,
+        }
+    }
+
+    pub fn is_set(&self) -> bool
+    {
+        *self == Set || *self == PermanentlySet
+    }
+}
+
+impl ::std::fmt::Show for ModeState
+{
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        let s = match *self
+        {
+            NotRecognized => "not recognized",
+            Set => "set",
+            Reset => "reset",
+            PermanentlySet => "permanently set",
+            PermanentlyReset => "permanently reset",
+        };
+        write!(fmt, "{}", s)
+    }
+}
+
+// Modes enabled through here are remembered, so that they can be put
+// back the way they were around a suspend (see suspend.rs).
+impl TermKey
+{
+    // SM/DECSET
+    pub fn enable_mode(&mut self, mode: Mode)
+    {
+        let mode = mode.normalize();
+        self.write_request(format!("\x1b[{}h", mode.param()).as_bytes());
+        if !self.modes.contains(&mode)
+        {
+            self.modes.push(mode);
+        }
+    }
+
+    // RM/DECRST
+    pub fn disable_mode(&mut self, mode: Mode)
+    {
+        let mode = mode.normalize();
+        self.write_request(format!("\x1b[{}l", mode.param()).as_bytes());
+        self.modes.retain(|m| *m != mode);
+    }
+
+    // in the order they were enabled
+    pub fn enabled_modes(&self) -> &[Mode]
+    {
+        self.modes.as_slice()
+    }
+}
@@ -0,0 +1,44 @@
+// Just enough of <poll.h> to wait on the terminal with a timeout.
+
+use libc::c_short;
+use libc::c_int;
+use libc::c_ulong;
+
+pub static POLLIN: c_short = 0x001;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct pollfd
+{
+    pub fd: c_int,
+    pub events: c_short,
+    pub revents: c_short,
+}
+
+extern
+{
+    pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+// A negative fd is ignored by poll(), so this just sleeps for waittime.
+pub fn poll_rd1(fd: c_int, waittime: int) -> int
+{
+    let mut pfd = pollfd{fd: fd, events: POLLIN, revents: 0};
+    unsafe
+    {
+        poll(&mut pfd, 1, waittime as c_int) as int
+    }
+}
+
+// As poll_rd1, but also wakes up for fd2, e.g. the signal pipe.
+// Returns the poll() result, and whether fd itself is readable.
+pub fn poll_rd2(fd: c_int, fd2: c_int, waittime: int) -> (int, bool)
+{
+    let mut pfds = [pollfd{fd: fd, events: POLLIN, revents: 0},
+            pollfd{fd: fd2, events: POLLIN, revents: 0}];
+    unsafe
+    {
+        let ret = poll(&mut pfds[0], 2, waittime as c_int) as int;
+        (ret, ret > 0 && pfds[0].revents != 0)
+    }
+}
@@ -0,0 +1,162 @@
+// TermKey over any Reader, rather than just a raw fd.
+//
+// With an fd to poll, waitkey works exactly as for a terminal: the fd
+// says when to read, and the reader does the reading (so a socket, a
+// pipe or a child's output all work). Without one, there's nothing to
+// wait on but the reader itself, so waitkey just reads until it has a
+// whole key, which suits recorded input.
+//
+// A Reader with an fd mustn't buffer: whatever it has read ahead is
+// invisible to poll, so waitkey would sit waiting with a key already
+// there. So use stdin_raw() rather than stdin(), and no BufferedReader.
+
+use std::io::{IoError, IoResult, Reader};
+use libc;
+use libc::c_int;
+
+use TermKey;
+use c;
+
+// Something with an fd that can be polled, and that reads from it
+// directly. (std doesn't have this; FdReader, WithFd and stdin_raw()
+// have it here.)
+pub trait AsRawFd
+{
+    fn as_raw_fd(&self) -> c_int;
+}
+
+impl AsRawFd for ::std::io::stdio::StdReader
+{
+    fn as_raw_fd(&self) -> c_int
+    {
+        libc::STDIN_FILENO
+    }
+}
+
+// Any Reader along with the fd it reads from, e.g. a PipeStream, or a
+// TcpStream for a socket accepted elsewhere.
+pub struct WithFd<R>
+{
+    reader: R,
+    fd: c_int,
+}
+
+impl<R: Reader> WithFd<R>
+{
+    pub fn new(reader: R, fd: c_int) -> WithFd<R>
+    {
+        WithFd{reader: reader, fd: fd}
+    }
+}
+
+impl<R: Reader> Reader for WithFd<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint>
+    {
+        self.reader.read(buf)
+    }
+}
+
+impl<R> AsRawFd for WithFd<R>
+{
+    fn as_raw_fd(&self) -> c_int
+    {
+        self.fd
+    }
+}
+
+// A Reader over a plain fd.
+pub struct FdReader
+{
+    fd: c_int,
+    // close on drop
+    owned: bool,
+}
+
+impl FdReader
+{
+    // Takes ownership: the fd is closed on drop.
+    pub fn new(fd: c_int) -> FdReader
+    {
+        FdReader{fd: fd, owned: true}
+    }
+
+    // For an fd that belongs to someone else, e.g. 0.
+    pub fn borrowed(fd: c_int) -> FdReader
+    {
+        FdReader{fd: fd, owned: false}
+    }
+}
+
+impl Reader for FdReader
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint>
+    {
+        loop
+        {
+            let len = unsafe
+            {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len() as libc::size_t)
+            };
+            if len < 0
+            {
+                let errno = ::std::os::errno();
+                if errno == libc::EINTR as uint
+                {
+                    continue;
+                }
+                return Err(IoError::from_errno(errno, false));
+            }
+            if len == 0
+            {
+                return Err(::std::io::standard_error(::std::io::EndOfFile));
+            }
+            return Ok(len as uint);
+        }
+    }
+}
+
+impl AsRawFd for FdReader
+{
+    fn as_raw_fd(&self) -> c_int
+    {
+        self.fd
+    }
+}
+
+impl Drop for FdReader
+{
+    fn drop(&mut self)
+    {
+        if self.owned
+        {
+            unsafe
+            {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+impl TermKey
+{
+    // The reader's fd is set up like any other (so a tty goes into raw
+    // mode, unless NOTERMIOS), and the reader is dropped along with the
+    // TermKey, after the terminal has been put back. The reader must
+    // not buffer (see above).
+    pub fn from_reader<R: Reader + AsRawFd + 'static>(reader: R, flags: c::X_TermKey_Flag) -> TermKey
+    {
+        let mut tk = TermKey::new(reader.as_raw_fd(), flags);
+        tk.reader = Some(box reader as Box<Reader + 'static>);
+        tk
+    }
+
+    // For a Reader without an fd, such as a MemReader of recorded input;
+    // term is as for new_abstract.
+    pub fn from_push_reader<R: Reader + 'static>(reader: R, term: &str, flags: c::X_TermKey_Flag) -> TermKey
+    {
+        let mut tk = TermKey::new_abstract(term, flags);
+        tk.reader = Some(box reader as Box<Reader + 'static>);
+        tk
+    }
+}
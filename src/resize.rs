@@ -0,0 +1,133 @@
+// Window size changes, as ResizeEvent.
+//
+// Terminals that support mode 2048 report the new size in-band, which
+// also works over ssh and doesn't need a signal handler. Anywhere else
+// SIGWINCH is caught, and the size read back with TIOCGWINSZ.
+
+use std::collections::Deque;
+use libc::{c_int, c_ulong, c_ushort};
+
+use c;
+use mode;
+use signal;
+use {TermKey, TermKeyEvent, ResizeEvent};
+
+#[cfg(all(any(target_os = "linux", target_os = "android"),
+        not(any(target_arch = "mips", target_arch = "mipsel"))))]
+static TIOCGWINSZ: c_ulong = 0x5413;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly",
+        all(any(target_os = "linux", target_os = "android"), any(target_arch = "mips", target_arch = "mipsel"))))]
+static TIOCGWINSZ: c_ulong = 0x40087468;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct winsize
+{
+    ws_row: c_ushort,
+    ws_col: c_ushort,
+    ws_xpixel: c_ushort,
+    ws_ypixel: c_ushort,
+}
+
+extern
+{
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+// The current size of the terminal on fd, if it is one.
+pub fn window_size(fd: c_int) -> Option<TermKeyEvent>
+{
+    if fd < 0
+    {
+        return None;
+    }
+    let mut ws = winsize{ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0};
+    unsafe
+    {
+        if ioctl(fd, TIOCGWINSZ, &mut ws as *mut winsize) != 0
+        {
+            return None;
+        }
+    }
+    Some(ResizeEvent{rows: ws.ws_row as int, cols: ws.ws_col as int,
+            pixel_width: ws.ws_xpixel as int, pixel_height: ws.ws_ypixel as int})
+}
+
+// CSI 48 ; rows ; cols ; height ; width t
+pub fn from_csi(cmd: c::c_ulong, args: &[c::c_long]) -> Option<TermKeyEvent>
+{
+    if cmd != 't' as c::c_ulong || args.len() < 3 || args[0] != 48
+    {
+        return None;
+    }
+    Some(ResizeEvent{rows: arg(args, 1), cols: arg(args, 2),
+            pixel_width: arg(args, 4), pixel_height: arg(args, 3)})
+}
+
+// as in da.rs
+fn arg(args: &[c::c_long], i: uint) -> int
+{
+    if i < args.len() && args[i] >= 0 { args[i] as int } else { 0 }
+}
+
+impl TermKey
+{
+    // Start reporting size changes as ResizeEvent. Returns true if the
+    // terminal does it in-band; the first report is the current size.
+    // If they are already on, this only says which way.
+    pub fn enable_resize_events(&mut self, timeout_ms: int) -> bool
+    {
+        if self.winch
+        {
+            return false;
+        }
+        if self.modes.contains(&mode::InBandResize)
+        {
+            return true;
+        }
+        match self.query_mode(mode::InBandResize, timeout_ms)
+        {
+            Some(mode::Set) | Some(mode::Reset) =>
+            {
+                // the terminal answers this with a report straight away
+                self.enable_mode(mode::InBandResize);
+                true
+            }
+            _ =>
+            {
+                self.catch_signal(signal::SIGWINCH);
+                self.winch = true;
+                let fd = self.get_fd() as c_int;
+                match window_size(fd)
+                {
+                    Some(key) => self.queue.push_back(key),
+                    None => {}
+                }
+                false
+            }
+        }
+    }
+
+    // Undoes whichever of the two enable_resize_events chose; nothing,
+    // if they aren't on.
+    pub fn disable_resize_events(&mut self)
+    {
+        if self.winch
+        {
+            signal::release(signal::SIGWINCH);
+            self.winch = false;
+        }
+        else if self.modes.contains(&mode::InBandResize)
+        {
+            self.disable_mode(mode::InBandResize);
+        }
+    }
+
+    // The size according to TIOCGWINSZ, whether or not resize events
+    // are enabled; None if the fd isn't a terminal.
+    pub fn get_window_size(&mut self) -> Option<TermKeyEvent>
+    {
+        let fd = self.get_fd() as c_int;
+        window_size(fd)
+    }
+}
@@ -0,0 +1,265 @@
+// Signals, turned into something that can be polled: the handler counts
+// each signal, and writes a byte to the pipe of every Watcher, so that
+// waitkey wakes up. The handlers are process-wide; each Watcher keeps
+// its own counts of what it has seen, so no TermKey takes another's.
+
+use std::sync::atomic::{AtomicInt, INIT_ATOMIC_INT, AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+use libc;
+use libc::c_int;
+
+pub use self::os::{SIGCONT, SIGTSTP, SIGWINCH};
+
+#[cfg(all(any(target_os = "linux", target_os = "android"),
+        not(any(target_arch = "mips", target_arch = "mipsel"))))]
+mod os
+{
+    use libc::c_int;
+
+    pub static SIGCONT: c_int = 18;
+    pub static SIGTSTP: c_int = 20;
+    pub static SIGWINCH: c_int = 28;
+    pub static O_NONBLOCK: c_int = 0o4000;
+}
+
+#[cfg(all(any(target_os = "linux", target_os = "android"),
+        any(target_arch = "mips", target_arch = "mipsel")))]
+mod os
+{
+    use libc::c_int;
+
+    pub static SIGCONT: c_int = 25;
+    pub static SIGTSTP: c_int = 24;
+    pub static SIGWINCH: c_int = 20;
+    pub static O_NONBLOCK: c_int = 0x80;
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+mod os
+{
+    use libc::c_int;
+
+    pub static SIGCONT: c_int = 19;
+    pub static SIGTSTP: c_int = 18;
+    pub static SIGWINCH: c_int = 28;
+    pub static O_NONBLOCK: c_int = 0x0004;
+}
+
+// the same everywhere
+static F_SETFD: c_int = 2;
+static F_GETFL: c_int = 3;
+static F_SETFL: c_int = 4;
+static FD_CLOEXEC: c_int = 1;
+
+static SIG_DFL: libc::size_t = 0;
+
+extern
+{
+    fn signal(signum: c_int, handler: libc::size_t) -> libc::size_t;
+    fn raise(sig: c_int) -> c_int;
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+#[cfg(target_os = "linux")]
+extern
+{
+    #[link_name = "__errno_location"]
+    fn errno_location() -> *mut c_int;
+}
+
+#[cfg(target_os = "android")]
+extern
+{
+    #[link_name = "__errno"]
+    fn errno_location() -> *mut c_int;
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+extern
+{
+    #[link_name = "__error"]
+    fn errno_location() -> *mut c_int;
+}
+
+struct Slot
+{
+    // how many times the signal has been caught
+    count: AtomicUint,
+    // how many catch() calls without a release()
+    users: AtomicUint,
+    // the handler from before the first catch()
+    old: AtomicUint,
+}
+
+static WINCH: Slot = Slot{count: INIT_ATOMIC_UINT, users: INIT_ATOMIC_UINT, old: INIT_ATOMIC_UINT};
+static TSTP: Slot = Slot{count: INIT_ATOMIC_UINT, users: INIT_ATOMIC_UINT, old: INIT_ATOMIC_UINT};
+static CONT: Slot = Slot{count: INIT_ATOMIC_UINT, users: INIT_ATOMIC_UINT, old: INIT_ATOMIC_UINT};
+
+// the write end of each Watcher's pipe, plus one; 0 where free
+static WAKEUP: [AtomicInt, ..8] = [INIT_ATOMIC_INT, INIT_ATOMIC_INT, INIT_ATOMIC_INT, INIT_ATOMIC_INT,
+        INIT_ATOMIC_INT, INIT_ATOMIC_INT, INIT_ATOMIC_INT, INIT_ATOMIC_INT];
+// handlers running now, which may still write to a pipe taken out of WAKEUP
+static ACTIVE: AtomicUint = INIT_ATOMIC_UINT;
+
+// SIGWINCH, SIGTSTP and SIGCONT are the only ones caught
+fn slot(sig: c_int) -> (uint, &'static Slot)
+{
+    if sig == SIGWINCH
+    {
+        (0, &WINCH)
+    }
+    else if sig == SIGTSTP
+    {
+        (1, &TSTP)
+    }
+    else if sig == SIGCONT
+    {
+        (2, &CONT)
+    }
+    else
+    {
+        panic!()
+    }
+}
+
+extern "C" fn handler(sig: c_int)
+{
+    let (_, slot) = slot(sig);
+    slot.count.fetch_add(1, SeqCst);
+    ACTIVE.fetch_add(1, SeqCst);
+    unsafe
+    {
+        // write() may clobber errno under whatever was interrupted
+        let saved = *errno_location();
+        let b = sig as u8;
+        for fd in WAKEUP.iter()
+        {
+            let fd = fd.load(SeqCst) - 1;
+            if fd >= 0
+            {
+                libc::write(fd as c_int, &b as *const u8 as *const libc::c_void, 1);
+            }
+        }
+        *errno_location() = saved;
+    }
+    ACTIVE.fetch_sub(1, SeqCst);
+}
+
+// Start turning sig into a wakeup.
+pub fn catch(sig: c_int)
+{
+    let (_, slot) = slot(sig);
+    if slot.users.fetch_add(1, SeqCst) == 0
+    {
+        unsafe
+        {
+            let h: libc::size_t = ::std::mem::transmute(handler);
+            slot.old.store(signal(sig, h) as uint, SeqCst);
+        }
+    }
+}
+
+// Once every catch() is released, back to the handler from before.
+pub fn release(sig: c_int)
+{
+    let (_, slot) = slot(sig);
+    if slot.users.fetch_sub(1, SeqCst) == 1
+    {
+        unsafe
+        {
+            signal(sig, slot.old.load(SeqCst) as libc::size_t);
+        }
+    }
+}
+
+// Send sig to ourselves with the default action, then put back whatever
+// handler there was (e.g. SIGTSTP, which only returns once we're continued).
+pub fn raise_default(sig: c_int)
+{
+    unsafe
+    {
+        let old = signal(sig, SIG_DFL);
+        raise(sig);
+        signal(sig, old);
+    }
+}
+
+// One TermKey's view of the signals: a pipe to poll, and the counts it
+// has already seen.
+pub struct Watcher
+{
+    pipe: [c_int, ..2],
+    // where the write end is in WAKEUP, if there was room
+    index: Option<uint>,
+    seen: [uint, ..3],
+}
+
+impl Watcher
+{
+    pub fn new() -> Watcher
+    {
+        let mut pipe = [-1, -1];
+        unsafe
+        {
+            if libc::pipe(pipe.as_mut_ptr()) != 0
+            {
+                panic!()
+            }
+            for &fd in pipe.iter()
+            {
+                let flags = fcntl(fd, F_GETFL);
+                fcntl(fd, F_SETFL, flags | os::O_NONBLOCK);
+                fcntl(fd, F_SETFD, FD_CLOEXEC);
+            }
+        }
+        let index = range(0, WAKEUP.len()).find(|&i| WAKEUP[i].compare_and_swap(0, pipe[1] as int + 1, SeqCst) == 0);
+        Watcher{pipe: pipe, index: index,
+                seen: [WINCH.count.load(SeqCst), TSTP.count.load(SeqCst), CONT.count.load(SeqCst)]}
+    }
+
+    // The read end of the pipe; -1 if there wasn't room for another,
+    // in which case signals don't wake up waitkey.
+    pub fn wakeup_fd(&self) -> c_int
+    {
+        if self.index.is_some() { self.pipe[0] } else { -1 }
+    }
+
+    // Empty the pipe, before looking at any of the counts, so that a
+    // signal after that leaves a byte for next time.
+    pub fn drain(&mut self)
+    {
+        let mut buf = [0u8, ..64];
+        unsafe
+        {
+            while libc::read(self.pipe[0], buf.as_mut_ptr() as *mut libc::c_void, 64) > 0 {}
+        }
+    }
+
+    // Has sig arrived since the last call?
+    pub fn take(&mut self, sig: c_int) -> bool
+    {
+        let (i, slot) = slot(sig);
+        let count = slot.count.load(SeqCst);
+        let new = count != self.seen[i];
+        self.seen[i] = count;
+        new
+    }
+}
+
+impl Drop for Watcher
+{
+    fn drop(&mut self)
+    {
+        match self.index
+        {
+            Some(i) => { WAKEUP[i].store(0, SeqCst); }
+            None => {}
+        }
+        // so that no handler writes to whatever gets the fd next
+        while ACTIVE.load(SeqCst) != 0 {}
+        unsafe
+        {
+            libc::close(self.pipe[0]);
+            libc::close(self.pipe[1]);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Job control: leaving the terminal usable for the shell while stopped,
+// and taking it back afterwards.
+//
+// libtermkey turns off VSUSP while started, so Ctrl-Z arrives as a key,
+// and it's up to the application to call suspend() for it. Catching the
+// signals is for stops that come from elsewhere, e.g. kill -TSTP.
+
+use std::collections::Deque;
+
+use mode;
+use signal;
+use {TermKey, ResumeEvent};
+
+impl TermKey
+{
+    // Put the terminal back, stop the process, and once it is continued,
+    // set everything up again and queue a ResumeEvent so the application
+    // knows to redraw.
+    pub fn suspend(&mut self)
+    {
+        let started = self.is_started();
+        let modes: Vec<mode::Mode> = self.enabled_modes().to_vec();
+        for m in modes.iter().rev()
+        {
+            self.write_request(format!("\x1b[{}l", m.param()).as_bytes());
+        }
+        if started
+        {
+            self.stop();
+        }
+
+        signal::raise_default(signal::SIGTSTP);
+
+        // the SIGCONT that got us here is handled already
+        self.take_signal(signal::SIGCONT);
+        if started
+        {
+            self.start();
+        }
+        for m in modes.iter()
+        {
+            self.write_request(format!("\x1b[{}h", m.param()).as_bytes());
+        }
+        self.queue.push_back(ResumeEvent);
+    }
+
+    // After being stopped by something else (e.g. SIGSTOP), whatever
+    // the shell did to the terminal meanwhile has to be undone.
+    pub fn resume(&mut self)
+    {
+        if self.is_started()
+        {
+            self.stop();
+            self.start();
+        }
+        let modes: Vec<mode::Mode> = self.enabled_modes().to_vec();
+        for m in modes.iter()
+        {
+            self.write_request(format!("\x1b[{}h", m.param()).as_bytes());
+        }
+        self.queue.push_back(ResumeEvent);
+    }
+
+    // Catch SIGTSTP and SIGCONT, and handle them with suspend() and
+    // resume() from getkey/waitkey. Every TermKey doing this would stop
+    // the process for itself, so only one should.
+    pub fn enable_job_control(&mut self)
+    {
+        if self.job_control
+        {
+            return;
+        }
+        self.catch_signal(signal::SIGTSTP);
+        self.catch_signal(signal::SIGCONT);
+        self.job_control = true;
+    }
+
+    pub fn disable_job_control(&mut self)
+    {
+        if self.job_control
+        {
+            signal::release(signal::SIGTSTP);
+            signal::release(signal::SIGCONT);
+            self.job_control = false;
+        }
+    }
+}
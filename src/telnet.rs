@@ -0,0 +1,368 @@
+// A telnet front end: takes what a telnet client sends, strips out the
+// protocol, and feeds the rest to a TermKey.
+//
+// Window sizes (NAWS, RFC 1073) become ResizeEvent, and the terminal
+// type (TTYPE, RFC 1091) chooses the term for new_abstract. A lone CR is
+// sent as CR NUL or CR LF (RFC 854), which is just Enter here.
+
+use std::collections::{Deque, RingBuf};
+
+use {TermKey, TermKeyResult, ResizeEvent};
+use c;
+
+static IAC: u8 = 255;
+static DONT: u8 = 254;
+static DO: u8 = 253;
+static WONT: u8 = 252;
+static WILL: u8 = 251;
+static SB: u8 = 250;
+static SE: u8 = 240;
+
+static OPT_ECHO: u8 = 1;
+static OPT_SGA: u8 = 3;
+static OPT_TTYPE: u8 = 24;
+static OPT_NAWS: u8 = 31;
+
+static TTYPE_IS: u8 = 0;
+static TTYPE_SEND: u8 = 1;
+
+// longer than any NAWS or TTYPE (whose names are at most 40 characters)
+static SUB_LIMIT: uint = 64;
+
+#[deriving(PartialEq)]
+enum State
+{
+    Normal,
+    // after CR, where NUL or LF is dropped
+    AfterCr,
+    Iac,
+    // WILL/WONT/DO/DONT, waiting for the option
+    Negotiate(u8),
+    // IAC SB, and then the subnegotiation until IAC SE
+    Sub,
+    SubIac,
+}
+
+// What came out of the protocol, in order.
+pub enum TelnetEvent
+{
+    Data(Vec<u8>),
+    WindowSize(u16, u16),
+    TerminalType(String),
+}
+
+// The protocol alone, without a TermKey.
+pub struct TelnetDecoder
+{
+    state: State,
+    sub: Vec<u8>,
+    // sub is longer than SUB_LIMIT, and will be dropped
+    overflow: bool,
+    // to be sent back to the client
+    replies: Vec<u8>,
+}
+
+impl TelnetDecoder
+{
+    pub fn new() -> TelnetDecoder
+    {
+        TelnetDecoder{state: Normal, sub: Vec::new(), overflow: false, replies: Vec::new()}
+    }
+
+    // What the server says first: it will echo and suppress go-ahead
+    // (i.e. character at a time), and would like NAWS and TTYPE.
+    pub fn greeting() -> Vec<u8>
+    {
+        vec![IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA, IAC, DO, OPT_NAWS, IAC, DO, OPT_TTYPE]
+    }
+
+    // Anything that needs to go back to the client now.
+    pub fn take_replies(&mut self) -> Vec<u8>
+    {
+        ::std::mem::replace(&mut self.replies, Vec::new())
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<TelnetEvent>
+    {
+        let mut events = Vec::new();
+        let mut data = Vec::new();
+        for &b in bytes.iter()
+        {
+            match self.state
+            {
+                Normal | AfterCr =>
+                {
+                    let after_cr = self.state == AfterCr;
+                    self.state = Normal;
+                    if b == IAC
+                    {
+                        self.state = Iac;
+                    }
+                    else if after_cr && (b == 0 || b == b'\n')
+                    {
+                        // the rest of the CR
+                    }
+                    else
+                    {
+                        data.push(b);
+                        if b == b'\r'
+                        {
+                            self.state = AfterCr;
+                        }
+                    }
+                }
+                Iac =>
+                {
+                    self.state = Normal;
+                    if b == IAC
+                    {
+                        data.push(IAC);
+                    }
+                    else if b == SB
+                    {
+                        self.sub.clear();
+                        self.overflow = false;
+                        self.state = Sub;
+                    }
+                    else if b >= WILL
+                    {
+                        self.state = Negotiate(b);
+                    }
+                    // NOP, AYT, GA and the like are just dropped
+                }
+                Negotiate(cmd) =>
+                {
+                    self.state = Normal;
+                    self.negotiate(cmd, b);
+                }
+                Sub =>
+                {
+                    if b == IAC
+                    {
+                        self.state = SubIac;
+                    }
+                    else
+                    {
+                        self.sub_push(b);
+                    }
+                }
+                SubIac =>
+                {
+                    if b == SE
+                    {
+                        self.state = Normal;
+                        match self.subnegotiation()
+                        {
+                            Some(event) =>
+                            {
+                                flush(&mut data, &mut events);
+                                events.push(event);
+                            }
+                            None => {}
+                        }
+                    }
+                    else
+                    {
+                        // IAC IAC inside is a literal 255
+                        self.sub_push(b);
+                        self.state = Sub;
+                    }
+                }
+            }
+        }
+        flush(&mut data, &mut events);
+        events
+    }
+
+    fn sub_push(&mut self, b: u8)
+    {
+        if self.sub.len() < SUB_LIMIT
+        {
+            self.sub.push(b);
+        }
+        else
+        {
+            self.overflow = true;
+        }
+    }
+
+    fn negotiate(&mut self, cmd: u8, opt: u8)
+    {
+        if cmd == WILL
+        {
+            if opt == OPT_TTYPE
+            {
+                self.replies.push_all(&[IAC, SB, OPT_TTYPE, TTYPE_SEND, IAC, SE]);
+            }
+            else if opt != OPT_NAWS
+            {
+                self.replies.push_all(&[IAC, DONT, opt]);
+            }
+        }
+        else if cmd == DO
+        {
+            if opt != OPT_ECHO && opt != OPT_SGA
+            {
+                self.replies.push_all(&[IAC, WONT, opt]);
+            }
+        }
+        // WONT and DONT need no answer
+    }
+
+    fn subnegotiation(&mut self) -> Option<TelnetEvent>
+    {
+        if self.overflow
+        {
+            return None;
+        }
+        let sub = self.sub.as_slice();
+        if sub.len() == 5 && sub[0] == OPT_NAWS
+        {
+            let cols = (sub[1] as u16 << 8) | sub[2] as u16;
+            let rows = (sub[3] as u16 << 8) | sub[4] as u16;
+            return Some(WindowSize(rows, cols));
+        }
+        if sub.len() >= 2 && sub[0] == OPT_TTYPE && sub[1] == TTYPE_IS
+        {
+            let name = String::from_utf8_lossy(sub.slice_from(2)).into_string();
+            let name: String = name.as_slice().chars().map(|c| c.to_lowercase()).collect();
+            return Some(TerminalType(name));
+        }
+        None
+    }
+}
+
+fn flush(data: &mut Vec<u8>, events: &mut Vec<TelnetEvent>)
+{
+    if !data.is_empty()
+    {
+        events.push(Data(::std::mem::replace(data, Vec::new())));
+    }
+}
+
+// One client's TermKey, behind a TelnetDecoder.
+pub struct TelnetClient
+{
+    tk: TermKey,
+    decoder: TelnetDecoder,
+    flags: c::X_TermKey_Flag,
+    term: String,
+    // what there wasn't room for in the TermKey yet
+    pending: RingBuf<TelnetEvent>,
+}
+
+impl TelnetClient
+{
+    // The term is "vt100" until the client says otherwise.
+    pub fn new(flags: c::X_TermKey_Flag) -> TelnetClient
+    {
+        TelnetClient{tk: TermKey::new_abstract("vt100", flags), decoder: TelnetDecoder::new(),
+                flags: flags, term: "vt100".to_string(), pending: RingBuf::new()}
+    }
+
+    pub fn greeting(&self) -> Vec<u8>
+    {
+        TelnetDecoder::greeting()
+    }
+
+    // Takes what was read from the client, and returns what should be
+    // written back to it. Whatever the TermKey has no room for waits
+    // for getkey; while has_pending(), there's no point reading more.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Vec<u8>
+    {
+        for event in self.decoder.feed(bytes).into_iter()
+        {
+            self.pending.push_back(event);
+        }
+        self.pass_on();
+        self.decoder.take_replies()
+    }
+
+    pub fn has_pending(&self) -> bool
+    {
+        !self.pending.is_empty()
+    }
+
+    // Pass on as much as the TermKey has room for, in order.
+    fn pass_on(&mut self)
+    {
+        loop
+        {
+            match self.pending.pop_front()
+            {
+                Some(Data(data)) =>
+                {
+                    let len = self.tk.push_bytes(data.as_slice());
+                    if len < data.len()
+                    {
+                        self.pending.push_front(Data(data.slice_from(len).to_vec()));
+                        return;
+                    }
+                }
+                Some(WindowSize(rows, cols)) =>
+                {
+                    self.tk.input.push_event(ResizeEvent{rows: rows as int, cols: cols as int,
+                            pixel_width: 0, pixel_height: 0});
+                }
+                Some(TerminalType(name)) =>
+                {
+                    // not while libtermkey has part of the input, which
+                    // there's no getting back out of it
+                    if self.tk.get_buffer_remaining() < self.tk.get_buffer_size()
+                    {
+                        self.pending.push_front(TerminalType(name));
+                        return;
+                    }
+                    self.set_term(name);
+                }
+                None => return,
+            }
+        }
+    }
+
+    // Clients normally answer TTYPE before sending any keys, but if not,
+    // this waits until libtermkey's buffer is empty. Everything else is
+    // carried over to the new TermKey.
+    fn set_term(&mut self, name: String)
+    {
+        if name == self.term
+        {
+            return;
+        }
+        let mut tk = TermKey::new_abstract(name.as_slice(), self.flags);
+        let old = &mut self.tk;
+        tk.set_flags(old.get_flags());
+        tk.set_waittime(old.get_waittime());
+        tk.set_canonflags(old.get_canonflags());
+        tk.set_buffer_size(old.get_buffer_size());
+        tk.queue = ::std::mem::replace(&mut old.queue, RingBuf::new());
+        tk.input = ::std::mem::replace(&mut old.input, ::input::InputFilter::new());
+        tk.modes = ::std::mem::replace(&mut old.modes, Vec::new());
+        tk.charset = old.charset.take();
+        *old = tk;
+        self.term = name;
+    }
+
+    pub fn get_term(&self) -> &str
+    {
+        self.term.as_slice()
+    }
+
+    pub fn getkey(&mut self) -> TermKeyResult
+    {
+        self.pass_on();
+        self.tk.getkey()
+    }
+
+    pub fn getkey_force(&mut self) -> TermKeyResult
+    {
+        self.pass_on();
+        self.tk.getkey_force()
+    }
+
+    // For strfkey and the rest.
+    pub fn termkey(&mut self) -> &mut TermKey
+    {
+        &mut self.tk
+    }
+}
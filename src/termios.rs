@@ -0,0 +1,237 @@
+// Which control keys reach us as keys, and which the tty driver keeps
+// for itself (as signals or flow control).
+//
+// By default libtermkey lets Ctrl-\, Ctrl-Z and Ctrl-S/Ctrl-Q through,
+// and Ctrl-C too with TERMKEY_FLAG_CTRLC. new_with_plain_keys() and
+// set_plain_keys() choose each one separately. The choice is applied on
+// top of libtermkey's own settings whenever it starts, and goes away
+// with them when it stops.
+
+use libc::c_int;
+
+use TermKey;
+use self::os::{tcflag_t, NCCS, VINTR, VQUIT, VSUSP, ISIG, ICANON, ECHO, IXON, POSIX_VDISABLE};
+
+bitset!(PlainKeys: c_int
+{
+    PLAIN_CTRL_C         = 1 << 0,  // VINTR, SIGINT
+    PLAIN_CTRL_BACKSLASH = 1 << 1,  // VQUIT, SIGQUIT
+    PLAIN_CTRL_Z         = 1 << 2,  // VSUSP, SIGTSTP
+    PLAIN_XON_XOFF       = 1 << 3   // IXON, Ctrl-S and Ctrl-Q
+})
+
+impl PlainKeys
+{
+    // what libtermkey does, depending on TERMKEY_FLAG_CTRLC
+    pub fn default(ctrlc: bool) -> PlainKeys
+    {
+        let keys = PLAIN_CTRL_BACKSLASH | PLAIN_CTRL_Z | PLAIN_XON_XOFF;
+        if ctrlc { keys | PLAIN_CTRL_C } else { keys }
+    }
+}
+
+/* Just enough of <termios.h>, for each OS */
+
+#[cfg(target_os = "linux")]
+#[allow(non_camel_case_types)]
+mod os
+{
+    pub type tcflag_t = u32;
+    pub type speed_t = u32;
+    pub const NCCS: uint = 32;
+
+    pub static VINTR: uint = 0;
+    pub static VQUIT: uint = 1;
+    pub static VSUSP: uint = 10;
+
+    pub static ISIG: tcflag_t = 0o1;
+    pub static ICANON: tcflag_t = 0o2;
+    pub static ECHO: tcflag_t = 0o10;
+    pub static IXON: tcflag_t = 0o2000;
+
+    pub static POSIX_VDISABLE: u8 = 0;
+}
+
+// bionic uses the kernel's struct, without the speeds
+#[cfg(target_os = "android")]
+#[allow(non_camel_case_types)]
+mod os
+{
+    pub type tcflag_t = u32;
+    pub const NCCS: uint = 19;
+
+    pub static VINTR: uint = 0;
+    pub static VQUIT: uint = 1;
+    pub static VSUSP: uint = 10;
+
+    pub static ISIG: tcflag_t = 0o1;
+    pub static ICANON: tcflag_t = 0o2;
+    pub static ECHO: tcflag_t = 0o10;
+    pub static IXON: tcflag_t = 0o2000;
+
+    pub static POSIX_VDISABLE: u8 = 0;
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+#[allow(non_camel_case_types)]
+mod os
+{
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub type tcflag_t = ::libc::c_ulong;
+    #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+    pub type tcflag_t = u32;
+    pub type speed_t = tcflag_t;
+    pub const NCCS: uint = 20;
+
+    pub static VINTR: uint = 8;
+    pub static VQUIT: uint = 9;
+    pub static VSUSP: uint = 10;
+
+    pub static ISIG: tcflag_t = 0x80;
+    pub static ICANON: tcflag_t = 0x100;
+    pub static ECHO: tcflag_t = 0x8;
+    pub static IXON: tcflag_t = 0x200;
+
+    pub static POSIX_VDISABLE: u8 = 0xff;
+}
+
+static TCSANOW: c_int = 0;
+
+#[repr(C)]
+pub struct Termios
+{
+    c_iflag: tcflag_t,
+    c_oflag: tcflag_t,
+    c_cflag: tcflag_t,
+    c_lflag: tcflag_t,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    c_line: u8,
+    c_cc: [u8, ..NCCS],
+    #[cfg(not(target_os = "android"))]
+    c_ispeed: os::speed_t,
+    #[cfg(not(target_os = "android"))]
+    c_ospeed: os::speed_t,
+}
+
+extern
+{
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+}
+
+impl Termios
+{
+    // line at a time, as a shell leaves it
+    pub fn canonical(&self) -> bool
+    {
+        self.c_lflag & ICANON != 0
+    }
+    pub fn echo(&self) -> bool
+    {
+        self.c_lflag & ECHO != 0
+    }
+}
+
+impl PartialEq for Termios
+{
+    fn eq(&self, other: &Termios) -> bool
+    {
+        self.c_iflag == other.c_iflag && self.c_oflag == other.c_oflag
+                && self.c_cflag == other.c_cflag && self.c_lflag == other.c_lflag
+                && self.c_cc.as_slice() == other.c_cc.as_slice()
+    }
+}
+
+pub fn get(fd: c_int) -> Option<Termios>
+{
+    if fd < 0
+    {
+        return None;
+    }
+    unsafe
+    {
+        // the fields differ by OS
+        let mut t: Termios = ::std::mem::zeroed();
+        if tcgetattr(fd, &mut t) != 0
+        {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+// Adjust the started terminal on fd, given how it was before starting.
+pub fn apply(fd: c_int, orig: &Termios, keys: PlainKeys)
+{
+    let mut t = match get(fd)
+    {
+        Some(t) => t,
+        None => return,
+    };
+
+    let signals = [(PLAIN_CTRL_C, VINTR), (PLAIN_CTRL_BACKSLASH, VQUIT), (PLAIN_CTRL_Z, VSUSP)];
+    if signals.iter().all(|&(key, _)| keys.contains(key))
+    {
+        t.c_lflag &= !ISIG;
+    }
+    else
+    {
+        t.c_lflag |= ISIG;
+    }
+    for &(key, v) in signals.iter()
+    {
+        t.c_cc[v] = if keys.contains(key) { POSIX_VDISABLE } else { orig.c_cc[v] };
+    }
+
+    if keys.contains(PLAIN_XON_XOFF)
+    {
+        t.c_iflag &= !IXON;
+    }
+    else
+    {
+        t.c_iflag |= IXON;
+    }
+
+    unsafe
+    {
+        tcsetattr(fd, TCSANOW, &t);
+    }
+}
+
+impl TermKey
+{
+    // As new, but with the plain keys chosen before libtermkey first
+    // changes the terminal, so that e.g. Ctrl-C is never a key, even
+    // for a moment.
+    pub fn new_with_plain_keys(fd: c_int, flags: ::c::X_TermKey_Flag, keys: PlainKeys) -> TermKey
+    {
+        // started, but with the terminal as it was
+        let mut tk = TermKey::new(fd, flags | ::c::TERMKEY_FLAG_NOTERMIOS);
+        tk.stop();
+        // less only what was added, not the UTF8 or RAW libtermkey chose
+        let flags = tk.get_flags() - ::c::TERMKEY_FLAG_NOTERMIOS;
+        tk.set_flags(flags);
+        tk.plain_keys = Some(keys);
+        tk.start();
+        tk
+    }
+
+    // Takes effect straight away if started, and again on every start.
+    pub fn set_plain_keys(&mut self, keys: PlainKeys)
+    {
+        self.plain_keys = Some(keys);
+        if self.is_started()
+        {
+            self.apply_plain_keys();
+        }
+    }
+
+    pub fn get_plain_keys(&mut self) -> PlainKeys
+    {
+        match self.plain_keys
+        {
+            Some(keys) => keys,
+            None => PlainKeys::default(self.get_flags().contains(::c::TERMKEY_FLAG_CTRLC)),
+        }
+    }
+}
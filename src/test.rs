@@ -451,7 +451,7 @@ fn test_03utf8()
         _ => { tap.bypass(3, "getkey yields RES_KEY UTF-8 4 high") }
     }
 
-    /* Invalid continuations */
+    /* Invalid continuations, each up to where it went wrong */
 
     tk.push_bytes([0xC2, '!' as u8]);
     match tk.getkey()
@@ -461,11 +461,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 2 invalid cont");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 2 invalid cont");
+                    tap.ok(bytes == vec![0xC2], "key.bytes UTF-8 2 invalid cont");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 2 invalid cont") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 2 invalid cont") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 2 invalid cont") }
@@ -495,11 +495,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 3 invalid cont");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 3 invalid cont");
+                    tap.ok(bytes == vec![0xE0], "key.bytes UTF-8 3 invalid cont");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 3 invalid cont") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 3 invalid cont") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 3 invalid cont") }
@@ -529,11 +529,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 3 invalid cont 2");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 3 invalid cont 2");
+                    tap.ok(bytes == vec![0xE0, 0xA0], "key.bytes UTF-8 3 invalid cont 2");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 3 invalid cont 2") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 3 invalid cont 2") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 3 invalid cont 2") }
@@ -563,11 +563,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 4 invalid cont");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 4 invalid cont");
+                    tap.ok(bytes == vec![0xF0], "key.bytes UTF-8 4 invalid cont");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 4 invalid cont") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 4 invalid cont") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 4 invalid cont") }
@@ -597,11 +597,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 4 invalid cont 2");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 4 invalid cont 2");
+                    tap.ok(bytes == vec![0xF0, 0x90], "key.bytes UTF-8 4 invalid cont 2");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 4 invalid cont 2") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 4 invalid cont 2") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 4 invalid cont 2") }
@@ -631,11 +631,11 @@ fn test_03utf8()
             tap.pass("getkey yields RES_KEY UTF-8 4 invalid cont 3");
             match key
             {
-                termkey::UnicodeEvent{codepoint, mods: _, utf8: _} =>
+                termkey::InvalidUtf8Event{bytes} =>
                 {
-                    tap.is_int(codepoint, '\uFFFD', "key.code.number UTF-8 4 invalid cont 3");
+                    tap.ok(bytes == vec![0xF0, 0x90, 0x80], "key.bytes UTF-8 4 invalid cont 3");
                 }
-                _ => { tap.bypass(1, "key.code.number UTF-8 4 invalid cont 3") }
+                _ => { tap.bypass(1, "key.bytes UTF-8 4 invalid cont 3") }
             }
         }
         _ => { tap.bypass(2, "getkey yields RES_KEY UTF-8 4 invalid cont 3") }
@@ -971,7 +971,7 @@ fn test_05read()
 fn test_06buffer()
 {
     let mut tap = taplib::Tap::new();
-    tap.plan_tests(9);
+    tap.plan_tests(13);
 
     let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
 
@@ -996,6 +996,23 @@ fn test_06buffer()
         }
         _ => { tap.bypass(1, "buffered key still useable after resize") }
     }
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+    let bytes: Vec<u8> = Vec::from_elem(300, 'a' as u8);
+
+    tap.is_int(tk.push_bytes(bytes.as_slice()), 256, "push_bytes takes only what fits");
+    tap.is_int(tk.push_bytes("b".as_bytes()), 0, "push_bytes returns 0 when full");
+
+    match tk.getkey()
+    {
+        termkey::Key(_) =>
+        {
+            tap.pass("getkey from a full buffer");
+        }
+        _ => { tap.fail("getkey from a full buffer") }
+    }
+
+    tap.is_int(tk.push_bytes("b".as_bytes()), 1, "push_bytes takes more after getkey");
 }
 
 pub fn breakpoint()
@@ -1059,79 +1076,79 @@ fn test_11strfkey()
 
     let key: termkey::TermKeyEvent = termkey::UnicodeEvent{codepoint: 'A', mods: termkey::c::X_TermKey_KeyMod::empty(), utf8: termkey::Utf8Char{bytes: [0, 0, 0, 0, 0, 0, 0]}};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 1, "length for unicode/A/0");
     tap.is_str(buffer, "A", "buffer for unicode/A/0");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
     tap.is_int(buffer.len(), 1, "length for unicode/A/0 wrapbracket");
     tap.is_str(buffer, "A", "buffer for unicode/A/0 wrapbracket");
 
     let key: termkey::TermKeyEvent = termkey::UnicodeEvent{codepoint: 'b', mods: termkey::c::TERMKEY_KEYMOD_CTRL, utf8: termkey::Utf8Char{bytes: [0, 0, 0, 0, 0, 0, 0]}};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 3, "length for unicode/b/CTRL");
     tap.is_str(buffer, "C-b", "buffer for unicode/b/CTRL");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD);
     tap.is_int(buffer.len(), 6, "length for unicode/b/CTRL longmod");
     tap.is_str(buffer, "Ctrl-b", "buffer for unicode/b/CTRL longmod");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_SPACEMOD);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_SPACEMOD);
     tap.is_int(buffer.len(), 6, "length for unicode/b/CTRL longmod|spacemod");
     tap.is_str(buffer, "Ctrl b", "buffer for unicode/b/CTRL longmod|spacemod");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_LOWERMOD);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_LOWERMOD);
     tap.is_int(buffer.len(), 6, "length for unicode/b/CTRL longmod|lowermod");
     tap.is_str(buffer, "ctrl-b", "buffer for unicode/b/CTRL longmod|lowermod");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_SPACEMOD|termkey::c::TERMKEY_FORMAT_LOWERMOD);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_SPACEMOD|termkey::c::TERMKEY_FORMAT_LOWERMOD);
     tap.is_int(buffer.len(), 6, "length for unicode/b/CTRL longmod|spacemod|lowermode");
     tap.is_str(buffer, "ctrl b", "buffer for unicode/b/CTRL longmod|spacemod|lowermode");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_CARETCTRL);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_CARETCTRL);
     tap.is_int(buffer.len(), 2, "length for unicode/b/CTRL caretctrl");
     tap.is_str(buffer, "^B", "buffer for unicode/b/CTRL caretctrl");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
     tap.is_int(buffer.len(), 5, "length for unicode/b/CTRL wrapbracket");
     tap.is_str(buffer, "<C-b>", "buffer for unicode/b/CTRL wrapbracket");
 
     let key: termkey::TermKeyEvent = termkey::UnicodeEvent{codepoint: 'c', mods: termkey::c::TERMKEY_KEYMOD_ALT, utf8: termkey::Utf8Char{bytes: [0, 0, 0, 0, 0, 0, 0]}};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 3, "length for unicode/c/ALT");
     tap.is_str(buffer, "A-c", "buffer for unicode/c/ALT");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD);
     tap.is_int(buffer.len(), 5, "length for unicode/c/ALT longmod");
     tap.is_str(buffer, "Alt-c", "buffer for unicode/c/ALT longmod");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_ALTISMETA);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_ALTISMETA);
     tap.is_int(buffer.len(), 3, "length for unicode/c/ALT altismeta");
     tap.is_str(buffer, "M-c", "buffer for unicode/c/ALT altismeta");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_ALTISMETA);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LONGMOD|termkey::c::TERMKEY_FORMAT_ALTISMETA);
     tap.is_int(buffer.len(), 6, "length for unicode/c/ALT longmod|altismeta");
     tap.is_str(buffer, "Meta-c", "buffer for unicode/c/ALT longmod|altismeta");
 
     let key: termkey::TermKeyEvent = termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_UP, mods: termkey::c::X_TermKey_KeyMod::empty()};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 2, "length for sym/Up/0");
     tap.is_str(buffer, "Up", "buffer for sym/Up/0");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
     tap.is_int(buffer.len(), 4, "length for sym/Up/0 wrapbracket");
     tap.is_str(buffer, "<Up>", "buffer for sym/Up/0 wrapbracket");
 
     let key: termkey::TermKeyEvent = termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_PAGEUP, mods: termkey::c::X_TermKey_KeyMod::empty()};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 6, "length for sym/PageUp/0");
     tap.is_str(buffer, "PageUp", "buffer for sym/PageUp/0");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
     tap.is_int(buffer.len(), 7, "length for sym/PageUp/0 lowerspace");
     tap.is_str(buffer, "page up", "buffer for sym/PageUp/0 lowerspace");
 
@@ -1147,26 +1164,26 @@ fn test_11strfkey()
         // strfkey internals are not exposed; this is done internally.
 
         /* If size of buffer is too small, strfkey should return something consistent */
-        let buffer = tk.strfkey(/*4*/ key, termkey::c::TermKeyFormat::empty());
+        let buffer = tk.strfkey(/*4*/ &key, termkey::c::TermKeyFormat::empty());
         tap.is_int(buffer.len(), 6, "length for sym/PageUp/0");
         tap.is_str(buffer, "Pag", "buffer of len 4 for sym/PageUp/0");
 
-        let buffer = tk.strfkey(/*4*/ key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
+        let buffer = tk.strfkey(/*4*/ &key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
         tap.is_int(buffer.len(), 7, "length for sym/PageUp/0 lowerspace");
         tap.is_str(buffer, "pag", "buffer of len 4 for sym/PageUp/0 lowerspace");
     }
 
     let key: termkey::TermKeyEvent = termkey::FunctionEvent{num: 5, mods: termkey::c::X_TermKey_KeyMod::empty()};
 
-    let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+    let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
     tap.is_int(buffer.len(), 2, "length for func/5/0");
     tap.is_str(buffer, "F5", "buffer for func/5/0");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_WRAPBRACKET);
     tap.is_int(buffer.len(), 4, "length for func/5/0 wrapbracket");
     tap.is_str(buffer, "<F5>", "buffer for func/5/0 wrapbracket");
 
-    let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
+    let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_LOWERSPACE);
     tap.is_int(buffer.len(), 2, "length for func/5/0 lowerspace");
     tap.is_str(buffer, "f5", "buffer for func/5/0 lowerspace");
 }
@@ -1564,11 +1581,11 @@ fn test_30mouse()
                         tap.is_int(col, 1, "mouse column for press");
                         tap.ok(mods.is_empty(), "modifiers for press");
 
-                        let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+                        let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
                         tap.is_int(buffer.len(), 13, "string length for press");
                         tap.is_str(buffer, "MousePress(1)", "string buffer for press");
 
-                        let buffer = tk.strfkey(key, termkey::c::TERMKEY_FORMAT_MOUSE_POS);
+                        let buffer = tk.strfkey(&key, termkey::c::TERMKEY_FORMAT_MOUSE_POS);
                         tap.is_int(buffer.len(), 21, "string length for press");
                         tap.is_str(buffer, "MousePress(1) @ (1,1)", "string buffer for press");
                     }
@@ -1647,7 +1664,7 @@ fn test_30mouse()
                         tap.is_int(col, 11, "mouse column for Ctrl-press");
                         tap.is_int(mods, termkey::c::TERMKEY_KEYMOD_CTRL, "modifiers for Ctrl-press");
 
-                        let buffer = tk.strfkey(key, termkey::c::TermKeyFormat::empty());
+                        let buffer = tk.strfkey(&key, termkey::c::TermKeyFormat::empty());
                         tap.is_int(buffer.len(), 15, "string length for Ctrl-press");
                         tap.is_str(buffer, "C-MousePress(1)", "string buffer for Ctrl-press");
                     }
@@ -1854,7 +1871,7 @@ fn test_31position()
 fn test_32modereport()
 {
     let mut tap = taplib::Tap::new();
-    tap.plan_tests(12);
+    tap.plan_tests(20);
 
     let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
 
@@ -1867,15 +1884,15 @@ fn test_32modereport()
             tap.pass("getkey yields RES_KEY for mode report");
             match key
             {
-                termkey::ModeReportEvent{initial, mode, value} =>
+                termkey::ModeReportEvent{mode, state} =>
                 {
                     tap.pass("key.type for mode report");
 
                     tap.pass("interpret_modereoprt yields RES_KEY");
 
-                    tap.is_int(initial, '?' as int, "initial indicator from mode report");
-                    tap.is_int(mode, 1, "mode number from mode report");
-                    tap.is_int(value, 2, "mode value from mode report");
+                    tap.ok(mode.is_dec(), "initial indicator from mode report");
+                    tap.is_int(mode, termkey::mode::CursorKeys, "mode number from mode report");
+                    tap.is_int(state, termkey::mode::Reset, "mode value from mode report");
                 }
                 _ => { tap.bypass(5, "key.type for mode report") }
             }
@@ -1893,21 +1910,40 @@ fn test_32modereport()
 
             match key
             {
-                termkey::ModeReportEvent{initial, mode, value} =>
+                termkey::ModeReportEvent{mode, state} =>
                 {
                     tap.pass("key.type for mode report");
 
                     tap.pass("interpret_modereoprt yields RES_KEY");
 
-                    tap.is_int(initial, 0, "initial indicator from mode report");
-                    tap.is_int(mode, 4, "mode number from mode report");
-                    tap.is_int(value, 1, "mode value from mode report");
+                    tap.ok(!mode.is_dec(), "initial indicator from mode report");
+                    tap.is_int(mode, termkey::mode::Insert, "mode number from mode report");
+                    tap.is_int(state, termkey::mode::Set, "mode value from mode report");
                 }
                 _ => { tap.bypass(5, "key.type for mode report") }
             }
         }
         _ => { tap.bypass(6, "getkey yields RES_KEY for mode report") }
     }
+
+    tk.push_bytes("\x1b[?2048;4$y".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "Mode(?2048=permanently reset)", "strfkey for mode report");
+        }
+        _ => { tap.fail("strfkey for mode report") }
+    }
+
+    tap.is_int(termkey::mode::Mode::new(true, 1234), termkey::mode::OtherDec(1234), "unknown DEC mode");
+    tap.is_int(termkey::mode::Mode::new(false, 1234), termkey::mode::Other(1234), "unknown ANSI mode");
+    tap.is_int(termkey::mode::Mode::from_c('?' as libc::c_int, 67584), None, "mode number out of range");
+    tap.is_int(termkey::mode::Mode::from_c('?' as libc::c_int, 2048), Some(termkey::mode::InBandResize), "mode number in range");
+    tap.is_int(termkey::mode::Mode::dec(1), termkey::mode::CursorKeys, "Mode::dec names known modes");
+    tap.is_int(termkey::mode::Mode::ansi(4), termkey::mode::Insert, "Mode::ansi names known modes");
+    tap.is_int(termkey::mode::OtherDec(1).normalize(), termkey::mode::CursorKeys, "normalize OtherDec(1)");
 }
 
 #[test]
@@ -1987,3 +2023,1892 @@ fn test_39csi()
         _ => { tap.bypass(4, "getkey yields RES_KEY for CSI ? $x") }
     }
 }
+
+#[test]
+fn test_40query()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(10);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("a\x1b[?15;7Rb".as_bytes());
+
+    match tk.query_cursor_position(0)
+    {
+        Some((line, col)) =>
+        {
+            tap.pass("query_cursor_position finds reply after a key");
+            tap.is_int(line, 15, "line for cursor position query");
+            tap.is_int(col, 7, "column for cursor position query");
+        }
+        None => { tap.bypass(3, "query_cursor_position finds reply after a key") }
+    }
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'a', "key before reply is queued");
+        }
+        _ => { tap.fail("key before reply is queued") }
+    }
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'b', "key after reply is still buffered");
+        }
+        _ => { tap.fail("key after reply is still buffered") }
+    }
+
+    tk.push_bytes("x\x1b[?1;2$y".as_bytes());
+
+    match tk.query_mode(termkey::mode::Other(1), 0)
+    {
+        None => { tap.pass("query_mode ignores DEC reply to ANSI query"); }
+        Some(_) => { tap.fail("query_mode ignores DEC reply to ANSI query") }
+    }
+
+    tk.push_bytes("\x1b[?1;2$y".as_bytes());
+
+    tap.is_int(tk.query_mode(termkey::mode::CursorKeys, 0), Some(termkey::mode::Reset), "query_mode value for DECCKM");
+
+    tk.push_bytes("\x1b[?1;1$y".as_bytes());
+
+    tap.is_int(tk.query_mode(termkey::mode::OtherDec(1), 0), Some(termkey::mode::Set), "query_mode OtherDec(1) matches DECCKM");
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'x', "key before unmatched reply is queued");
+        }
+        _ => { tap.fail("key before unmatched reply is queued") }
+    }
+
+    tap.ok(tk.query_cursor_position(10).is_none(), "query_cursor_position times out");
+}
+
+#[test]
+fn test_41devattrs()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(16);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("\x1b[?62;1;6;22c".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::DeviceAttributesEvent{attrs: termkey::da::Primary{class, features}}) =>
+        {
+            tap.pass("getkey yields primary DA");
+            tap.is_int(class, 62, "class from primary DA");
+            tap.ok(features == vec![1, 6, 22], "features from primary DA");
+        }
+        _ => { tap.bypass(3, "getkey yields primary DA") }
+    }
+
+    tk.push_bytes("\x1b[>41;354;0c".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::DeviceAttributesEvent{attrs: termkey::da::Secondary{terminal, firmware, rom}}) =>
+        {
+            tap.pass("getkey yields secondary DA");
+            tap.is_int(terminal, 41, "terminal from secondary DA");
+            tap.is_int(firmware, 354, "firmware from secondary DA");
+            tap.is_int(rom, 0, "rom from secondary DA");
+        }
+        _ => { tap.bypass(4, "getkey yields secondary DA") }
+    }
+
+    tk.push_bytes("\x1bP>|xterm(388)".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Again => { tap.pass("getkey yields RES_AGAIN for partial DCS"); }
+        _ => { tap.fail("getkey yields RES_AGAIN for partial DCS") }
+    }
+
+    tk.push_bytes("\x1b\\".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::TerminalVersionEvent{version}) =>
+        {
+            tap.is_str(version, "xterm(388)", "getkey yields XTVERSION");
+        }
+        _ => { tap.fail("getkey yields XTVERSION") }
+    }
+
+    tk.push_bytes("\x1bP!|7E565445\x1b\\".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::DeviceAttributesEvent{attrs: termkey::da::Tertiary{unit_id}}) =>
+        {
+            tap.is_str(unit_id, "7E565445", "getkey yields tertiary DA");
+        }
+        _ => { tap.fail("getkey yields tertiary DA") }
+    }
+
+    tk.push_bytes("\x1bP".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Again => { tap.pass("getkey yields RES_AGAIN for ESC P"); }
+        _ => { tap.fail("getkey yields RES_AGAIN for ESC P") }
+    }
+
+    match tk.getkey_force()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'P', "getkey_force yields P for ESC P");
+            tap.ok(mods == termkey::c::TERMKEY_KEYMOD_ALT, "getkey_force yields Alt for ESC P");
+        }
+        _ => { tap.bypass(2, "getkey_force yields Alt-P for ESC P") }
+    }
+
+    tk.push_bytes("\x1bP>|foot(1.16.2)\x1b\\k\x1bP!|00000000\x1b\\\x1b[>1;11600;0c\x1b[?65;1;9c".as_bytes());
+
+    let info = tk.probe_terminal(0);
+    tap.ok(info.version == Some("foot(1.16.2)".to_string()), "probe_terminal finds XTVERSION");
+    tap.ok(info.primary == Some(termkey::da::Primary{class: 65, features: vec![1, 9]}), "probe_terminal finds primary DA");
+    tap.ok(info.secondary.is_some() && info.tertiary.is_some(), "probe_terminal finds secondary and tertiary DA");
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'k', "key during probe is queued");
+        }
+        _ => { tap.fail("key during probe is queued") }
+    }
+}
+
+#[test]
+fn test_42ctrlstring()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(13);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("\x1b]11;rgb:ffff/ffff/dddd\x07".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::OscEvent{number, payload}) =>
+        {
+            tap.is_int(number, 11, "OSC number for BEL-terminated OSC");
+            tap.ok(payload.as_slice() == "rgb:ffff/ffff/dddd".as_bytes(), "OSC payload for BEL-terminated OSC");
+        }
+        _ => { tap.bypass(2, "getkey yields OSC for BEL-terminated OSC") }
+    }
+
+    tk.push_bytes("\x1b]10;rgb:0000/0000/0000\x1b\\".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::OscEvent{number, payload}) =>
+        {
+            tap.is_int(number, 10, "OSC number for ST-terminated OSC");
+            tap.ok(payload.as_slice() == "rgb:0000/0000/0000".as_bytes(), "OSC payload for ST-terminated OSC");
+        }
+        _ => { tap.bypass(2, "getkey yields OSC for ST-terminated OSC") }
+    }
+
+    tk.push_bytes("\x1bP1$r0;1m\x1b\\".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::DcsEvent{params, intermediates, payload}) =>
+        {
+            tap.ok(params == vec![1], "DCS params for DECRQSS");
+            tap.is_str(intermediates, "$r", "DCS intermediates for DECRQSS");
+            tap.ok(payload.as_slice() == "0;1m".as_bytes(), "DCS payload for DECRQSS");
+        }
+        _ => { tap.bypass(3, "getkey yields DCS for DECRQSS") }
+    }
+
+    tk.push_bytes("\x1bP1+r6b637575=1b4f41\x07".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::DcsEvent{params, intermediates, payload}) =>
+        {
+            tap.ok(params == vec![1], "DCS params for XTGETTCAP");
+            tap.is_str(intermediates, "+r", "DCS intermediates for XTGETTCAP");
+            tap.ok(payload.as_slice() == "6b637575=1b4f41".as_bytes(), "DCS payload for XTGETTCAP");
+        }
+        _ => { tap.bypass(3, "getkey yields DCS for XTGETTCAP") }
+    }
+
+    tk.set_string_limit(8);
+    tap.is_int(tk.get_string_limit(), 8, "get_string_limit after set_string_limit");
+
+    tk.push_bytes("\x1b]52;c;aGVsbG8gd29ybGQ=\x1b\\x".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'x', "overlong OSC is dropped");
+        }
+        _ => { tap.fail("overlong OSC is dropped") }
+    }
+
+    tk.push_bytes("a\x1b]1\x1b\\b".as_bytes());
+    tk.getkey();
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::OscEvent{number, payload: _}) =>
+        {
+            tap.is_int(number, 1, "OSC comes between the keys around it");
+        }
+        _ => { tap.fail("OSC comes between the keys around it") }
+    }
+}
+
+#[test]
+fn test_43color()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(11);
+
+    tap.is_int(termkey::color::Rgb::parse("rgb:ffff/8080/0000"), Some(termkey::color::Rgb{r: 0xffff, g: 0x8080, b: 0}), "parse 16-bit rgb");
+    tap.is_int(termkey::color::Rgb::parse("rgb:f/80/000"), Some(termkey::color::Rgb{r: 0xffff, g: 0x8080, b: 0}), "parse mixed-width rgb");
+    tap.is_int(termkey::color::Rgb::parse("rgb:ff/ff"), None, "parse rgb with two channels");
+    tap.is_int(termkey::color::Rgb::parse("#ffffff"), None, "parse non-rgb colour");
+
+    let grey = termkey::color::Rgb::from_rgb8(0x80, 0x80, 0x80).luminance();
+    tap.ok(grey > 0.21 && grey < 0.22, "luminance of sRGB grey is linear");
+    tap.ok((termkey::color::Rgb::from_rgb8(0xff, 0xff, 0xff).luminance() - 1.0).abs() < 1e-9, "luminance of white");
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("q\x1b]11;rgb:1c1c/1c1c/1c1c\x1b\\".as_bytes());
+
+    match tk.query_background(0)
+    {
+        Some(rgb) =>
+        {
+            tap.ok(rgb.is_dark(), "query_background finds a dark background");
+        }
+        None => { tap.fail("query_background finds a dark background") }
+    }
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'q', "key during colour query is queued");
+        }
+        _ => { tap.fail("key during colour query is queued") }
+    }
+
+    tk.push_bytes("\x1b]10;rgb:eeee/eeee/eeee\x07".as_bytes());
+
+    match tk.query_foreground(0)
+    {
+        Some(rgb) =>
+        {
+            tap.ok(!rgb.is_dark(), "query_foreground finds a light foreground");
+        }
+        None => { tap.fail("query_foreground finds a light foreground") }
+    }
+
+    tk.push_bytes("\x1b]4;3;rgb:cdcd/cdcd/0000\x1b\\\x1b]4;1;rgb:cdcd/0000/0000\x1b\\".as_bytes());
+
+    tap.is_int(tk.query_palette(1, 0), Some(termkey::color::Rgb::from_rgb8(0xcd, 0, 0)), "query_palette skips other indices");
+
+    tap.is_int(tk.query_background(10), None, "query_background times out");
+}
+
+#[test]
+fn test_44clipboard()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(7);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("v\x1b]52;c;aGVsbG8gd29ybGQ=\x07".as_bytes());
+
+    tap.ok(tk.request_clipboard("c", 0) == Some("hello world".as_bytes().to_vec()), "request_clipboard decodes base64 reply");
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'v', "key during clipboard request is queued");
+        }
+        _ => { tap.fail("key during clipboard request is queued") }
+    }
+
+    tk.push_bytes("\x1b]52;c;\x1b\\".as_bytes());
+
+    tap.ok(tk.request_clipboard("c", 0) == Some(Vec::new()), "request_clipboard yields empty clipboard");
+
+    tap.ok(tk.request_clipboard("c", 10).is_none(), "request_clipboard times out");
+
+    tk.push_bytes("\x1b]52;c;aGk=\x07".as_bytes());
+
+    tap.ok(tk.request_clipboard("c\x07\x1b]0;x", 0).is_none(), "request_clipboard refuses other selection characters");
+    tap.ok(tk.request_clipboard("c", 0) == Some("hi".as_bytes().to_vec()), "refused request_clipboard leaves the input alone");
+
+    tk.push_bytes("\x1b]52;p;eA==\x07\x1b]52;c;aGk=\x07".as_bytes());
+
+    tap.ok(tk.request_clipboard("c", 0) == Some("hi".as_bytes().to_vec()), "request_clipboard skips a reply for another selection");
+}
+
+#[test]
+fn test_45xtgettcap()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(9);
+
+    tap.ok(termkey::xtgettcap::key_for_capability("kf12") == Some(termkey::FunctionEvent{num: 12, mods: termkey::c::X_TermKey_KeyMod::empty()}), "kf12 is F12");
+    tap.ok(termkey::xtgettcap::key_for_capability("kLFT5") == Some(termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_LEFT, mods: termkey::c::TERMKEY_KEYMOD_CTRL}), "kLFT5 is Ctrl-Left");
+    tap.ok(termkey::xtgettcap::key_for_capability("kf64").is_none(), "kf64 is not a key");
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    // kf1 = ESC [ [ A, as on the linux console; kf2 unknown; then DA1
+    tk.push_bytes("\x1bP1+r6b6631=1b5b5b41\x1b\\\x1bP0+r6b6632\x1b\\x\x1b[?62c".as_bytes());
+
+    let caps = tk.query_capabilities(&["kf1", "kf2"], 0);
+    tap.ok(caps == vec![("kf1".to_string(), "\x1b[[A".as_bytes().to_vec())], "query_capabilities skips unknown names");
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, mods: _, utf8: _}) =>
+        {
+            tap.is_int(codepoint, 'x', "key during capability query is queued");
+        }
+        _ => { tap.fail("key during capability query is queued") }
+    }
+
+    tk.push_bytes("\x1bP1+r6b6631=1b5b5b41\x1b\\\x1bP1+r6b4c465435=1b5b313b3544\x1b\\\x1b[?62c".as_bytes());
+
+    tap.is_int(tk.probe_key_capabilities(0), 2u, "probe_key_capabilities learns 2 keys");
+
+    tk.push_bytes("\x1b[[A".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::FunctionEvent{num, mods: _}) =>
+        {
+            tap.is_int(num, 1, "learned kf1 yields F1");
+        }
+        _ => { tap.fail("learned kf1 yields F1") }
+    }
+
+    tk.push_bytes("\x1b[[".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Again => { tap.pass("getkey yields RES_AGAIN after partial learned key") }
+        _ => { tap.fail("getkey yields RES_AGAIN after partial learned key") }
+    }
+
+    tk.push_bytes("A".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::FunctionEvent{num, mods: _}) =>
+        {
+            tap.is_int(num, 1, "learned kf1 completes");
+        }
+        _ => { tap.fail("learned kf1 completes") }
+    }
+}
+
+mod pty
+{
+    use libc::{c_char, c_int, c_ulong, c_ushort};
+
+    pub static TIOCSWINSZ: c_ulong = 0x5414;
+    pub static F_GETFD: c_int = 1;
+    pub static F_GETFL: c_int = 3;
+    pub static F_SETFL: c_int = 4;
+    pub static FD_CLOEXEC: c_int = 1;
+    pub static O_NONBLOCK: c_int = 0o4000;
+    pub static SIGCONT: c_int = 18;
+    pub static SIGWINCH: c_int = 28;
+
+    #[repr(C)]
+    pub struct winsize
+    {
+        pub ws_row: c_ushort,
+        pub ws_col: c_ushort,
+        pub ws_xpixel: c_ushort,
+        pub ws_ypixel: c_ushort,
+    }
+
+    extern
+    {
+        pub fn posix_openpt(flags: c_int) -> c_int;
+        pub fn grantpt(fd: c_int) -> c_int;
+        pub fn unlockpt(fd: c_int) -> c_int;
+        pub fn ptsname(fd: c_int) -> *const c_char;
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+        pub fn kill(pid: c_int, sig: c_int) -> c_int;
+        pub fn getpid() -> c_int;
+        pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    }
+
+    // (master, slave)
+    pub fn open() -> (c_int, c_int)
+    {
+        unsafe
+        {
+            let master = posix_openpt(::libc::O_RDWR | 0o400 /* O_NOCTTY */);
+            assert!(master >= 0);
+            grantpt(master);
+            unlockpt(master);
+            let slave = ::libc::open(ptsname(master), ::libc::O_RDWR | 0o400, 0);
+            assert!(slave >= 0);
+            (master, slave)
+        }
+    }
+
+    // where the slave can be opened again
+    pub fn path(master: c_int) -> String
+    {
+        unsafe
+        {
+            ::std::c_str::CString::new(ptsname(master), false).as_str().unwrap().to_string()
+        }
+    }
+
+    pub fn set_nonblock(fd: c_int)
+    {
+        unsafe
+        {
+            let flags = fcntl(fd, F_GETFL);
+            fcntl(fd, F_SETFL, flags | O_NONBLOCK);
+        }
+    }
+
+    pub fn set_size(master: c_int, rows: u16, cols: u16)
+    {
+        let ws = winsize{ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0};
+        unsafe
+        {
+            ioctl(master, TIOCSWINSZ, &ws as *const winsize);
+        }
+    }
+
+    // A pty pair, to be a real terminal for a TermKey on the slave side.
+    // Make the TermKey after the Harness, so that it goes first.
+    pub struct Harness
+    {
+        pub master: c_int,
+        pub slave: c_int,
+    }
+
+    impl Harness
+    {
+        pub fn new() -> Harness
+        {
+            let (master, slave) = open();
+            set_size(master, 24, 80);
+            Harness{master: master, slave: slave}
+        }
+
+        pub fn termios(&self) -> ::termkey::termios::Termios
+        {
+            ::termkey::termios::get(self.slave).unwrap()
+        }
+
+        // Write each string to the master after its delay (in ms), in
+        // the background, as a terminal would send them. The writer has
+        // a master of its own, which outlives the Harness if need be.
+        pub fn script(&self, steps: &[(i64, &str)])
+        {
+            let master = unsafe { ::libc::dup(self.master) };
+            assert!(master >= 0);
+            let steps: Vec<(i64, String)> = steps.iter().map(|&(ms, s)| (ms, s.to_string())).collect();
+            spawn(proc()
+            {
+                for (ms, s) in steps.into_iter()
+                {
+                    ::std::io::timer::sleep(::std::time::Duration::milliseconds(ms));
+                    ::fd_write(master, s.as_slice());
+                }
+                unsafe
+                {
+                    ::libc::close(master);
+                }
+            });
+        }
+
+        // The next n keys from waitkey, as strfkey prints them; fewer if
+        // waitkey stops yielding keys.
+        pub fn keys(&self, tk: &mut ::termkey::TermKey, n: uint) -> Vec<String>
+        {
+            let mut keys = Vec::new();
+            while keys.len() < n
+            {
+                match tk.waitkey()
+                {
+                    ::termkey::Key(key) => keys.push(tk.strfkey(&key, ::termkey::c::TermKeyFormat::empty())),
+                    _ => break,
+                }
+            }
+            keys
+        }
+    }
+
+    impl Drop for Harness
+    {
+        fn drop(&mut self)
+        {
+            unsafe
+            {
+                ::libc::close(self.slave);
+                ::libc::close(self.master);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_46resize()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(13);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    tk.push_bytes("\x1b[?2048;2$y".as_bytes());
+
+    tap.ok(tk.enable_resize_events(0), "enable_resize_events uses in-band reports");
+
+    tk.push_bytes("\x1b[48;24;80;384;640t".as_bytes());
+
+    match tk.getkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "Resize(80x24)".to_string(), "strfkey of in-band resize");
+            match key
+            {
+                termkey::ResizeEvent{rows, cols, pixel_width, pixel_height} =>
+                {
+                    tap.ok(rows == 24 && cols == 80 && pixel_width == 640 && pixel_height == 384, "in-band resize size");
+                }
+                _ => { tap.fail("in-band resize size") }
+            }
+        }
+        _ => { tap.bypass(2, "getkey yields in-band resize") }
+    }
+
+    tap.ok(tk.enable_resize_events(0), "enable_resize_events again stays in-band");
+    tk.disable_resize_events();
+    tap.ok(tk.enabled_modes().is_empty(), "disable_resize_events turns off in-band reports");
+
+    let (master, slave) = pty::open();
+    pty::set_size(master, 25, 81);
+
+    let mut tk = termkey::TermKey::new(slave, termkey::c::TERMKEY_FLAG_NOTERMIOS);
+
+    tap.ok(!tk.enable_resize_events(0), "enable_resize_events falls back to SIGWINCH");
+
+    match tk.getkey()
+    {
+        termkey::Key(termkey::ResizeEvent{rows, cols, ..}) =>
+        {
+            tap.ok(rows == 25 && cols == 81, "initial size from TIOCGWINSZ");
+        }
+        _ => { tap.fail("initial size from TIOCGWINSZ") }
+    }
+
+    tk.enable_resize_events(0);
+    match tk.getkey()
+    {
+        termkey::None_ => { tap.pass("enable_resize_events again changes nothing") }
+        _ => { tap.fail("enable_resize_events again changes nothing") }
+    }
+
+    pty::set_size(master, 30, 100);
+    unsafe
+    {
+        pty::kill(pty::getpid(), pty::SIGWINCH);
+    }
+
+    match tk.waitkey()
+    {
+        termkey::Key(termkey::ResizeEvent{rows, cols, ..}) =>
+        {
+            tap.ok(rows == 30 && cols == 100, "waitkey yields size after SIGWINCH");
+        }
+        _ => { tap.fail("waitkey yields size after SIGWINCH") }
+    }
+
+    // each sees every signal, whichever looks first
+    let mut tk2 = termkey::TermKey::new(slave, termkey::c::TERMKEY_FLAG_NOTERMIOS);
+    tk2.enable_resize_events(0);
+    tk2.getkey();
+
+    pty::set_size(master, 31, 101);
+    unsafe
+    {
+        pty::kill(pty::getpid(), pty::SIGWINCH);
+    }
+
+    for (i, tk) in [&mut tk2, &mut tk].iter_mut().enumerate()
+    {
+        match tk.waitkey()
+        {
+            termkey::Key(termkey::ResizeEvent{rows, cols, ..}) =>
+            {
+                tap.ok(rows == 31 && cols == 101, format!("TermKey {} yields size after SIGWINCH", i + 1).as_slice());
+            }
+            _ => { tap.fail(format!("TermKey {} yields size after SIGWINCH", i + 1).as_slice()) }
+        }
+    }
+    drop(tk2);
+    pty::set_size(master, 30, 100);
+
+    tk.disable_resize_events();
+
+    match tk.get_window_size()
+    {
+        Some(termkey::ResizeEvent{rows, cols, ..}) =>
+        {
+            tap.ok(rows == 30 && cols == 100, "get_window_size");
+        }
+        _ => { tap.fail("get_window_size") }
+    }
+
+    match tk.getkey()
+    {
+        termkey::None_ => { tap.pass("no resize once disabled") }
+        _ => { tap.fail("no resize once disabled") }
+    }
+
+    unsafe
+    {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+fn fd_read(fd: libc::c_int) -> String
+{
+    let mut buf = [0u8, ..256];
+    let len = unsafe
+    {
+        libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, 256)
+    };
+    if len <= 0
+    {
+        return String::new();
+    }
+    String::from_utf8_lossy(buf.slice_to(len as uint)).into_string()
+}
+
+#[test]
+fn test_47suspend()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(7);
+
+    let (master, slave) = pty::open();
+
+    let mut tk = termkey::TermKey::new(slave, termkey::c::TERMKEY_FLAG_NOTERMIOS);
+
+    tk.enable_mode(termkey::mode::BracketedPaste);
+    tap.is_str(fd_read(master), "\x1b[?2004h", "enable_mode writes DECSET");
+    tap.ok(tk.enabled_modes().len() == 1 && tk.enabled_modes()[0] == termkey::mode::BracketedPaste, "enabled_modes after enable_mode");
+
+    tk.resume();
+    tap.is_str(fd_read(master), "\x1b[?2004h", "resume enables modes again");
+
+    match tk.getkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "Resume", "getkey yields ResumeEvent after resume");
+        }
+        _ => { tap.fail("getkey yields ResumeEvent after resume") }
+    }
+
+    tk.enable_job_control();
+    unsafe
+    {
+        pty::kill(pty::getpid(), pty::SIGCONT);
+    }
+    match tk.waitkey()
+    {
+        termkey::Key(termkey::ResumeEvent) => { tap.pass("waitkey yields ResumeEvent after SIGCONT") }
+        _ => { tap.fail("waitkey yields ResumeEvent after SIGCONT") }
+    }
+    tk.disable_job_control();
+
+    tk.disable_mode(termkey::mode::BracketedPaste);
+    // the SIGCONT re-enabled it first
+    tap.is_str(fd_read(master), "\x1b[?2004h\x1b[?2004l", "disable_mode writes DECRST");
+    tap.ok(tk.enabled_modes().is_empty(), "enabled_modes after disable_mode");
+
+    unsafe
+    {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+#[test]
+fn test_48plainkeys()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(6);
+
+    let (master, slave) = pty::open();
+
+    let mut tk = termkey::TermKey::new(slave, termkey::c::X_TermKey_Flag::empty());
+
+    tap.ok(tk.get_plain_keys() == termkey::termios::PlainKeys::default(false), "get_plain_keys is libtermkey's default");
+
+    tk.set_plain_keys(termkey::termios::PLAIN_CTRL_BACKSLASH | termkey::termios::PLAIN_XON_XOFF);
+    fd_write(master, "\x1ax");
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "x", "Ctrl-Z is kept by the tty driver");
+        }
+        _ => { tap.fail("Ctrl-Z is kept by the tty driver") }
+    }
+
+    tk.set_plain_keys(termkey::termios::PLAIN_CTRL_Z | termkey::termios::PLAIN_XON_XOFF);
+    fd_write(master, "\x1a");
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "C-z", "Ctrl-Z as a plain key");
+        }
+        _ => { tap.fail("Ctrl-Z as a plain key") }
+    }
+
+    tk.stop();
+    tk.start();
+    fd_write(master, "\x1cy");
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "y", "Ctrl-\\ is kept by the tty driver after restart");
+        }
+        _ => { tap.fail("Ctrl-\\ is kept by the tty driver after restart") }
+    }
+
+    drop(tk);
+
+    let mut tk = termkey::TermKey::new_with_plain_keys(slave, termkey::c::X_TermKey_Flag::empty(), termkey::termios::PLAIN_CTRL_Z);
+
+    tap.ok(tk.get_plain_keys() == termkey::termios::PLAIN_CTRL_Z, "get_plain_keys after new_with_plain_keys");
+
+    fd_write(master, "\x1c\x11z");
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) =>
+        {
+            tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "z", "Ctrl-\\ and Ctrl-Q kept from the start");
+        }
+        _ => { tap.fail("Ctrl-\\ and Ctrl-Q kept from the start") }
+    }
+
+    drop(tk);
+    unsafe
+    {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+#[test]
+fn test_49reader()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(7);
+
+    let recorded = std::io::MemReader::new("a\x1b[A".as_bytes().to_vec());
+    let mut tk = termkey::TermKey::from_push_reader(recorded, "vt100", termkey::c::X_TermKey_Flag::empty());
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "a", "waitkey reads a from MemReader") }
+        _ => { tap.fail("waitkey reads a from MemReader") }
+    }
+    match tk.waitkey()
+    {
+        termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "Up", "waitkey reads Up from MemReader") }
+        _ => { tap.fail("waitkey reads Up from MemReader") }
+    }
+    match tk.waitkey()
+    {
+        termkey::Eof => { tap.pass("waitkey yields RES_EOF at end of MemReader") }
+        _ => { tap.fail("waitkey yields RES_EOF at end of MemReader") }
+    }
+
+    let fd = unsafe { std::os::pipe().unwrap() };
+    let mut tk = termkey::TermKey::from_reader(termkey::reader::FdReader::new(fd.reader), termkey::c::TERMKEY_FLAG_NOTERMIOS);
+
+    fd_write(fd.writer, "b");
+    unsafe
+    {
+        libc::close(fd.writer);
+    }
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "b", "waitkey reads b from FdReader") }
+        _ => { tap.fail("waitkey reads b from FdReader") }
+    }
+    match tk.waitkey()
+    {
+        termkey::Eof => { tap.pass("waitkey yields RES_EOF once pipe is closed") }
+        _ => { tap.fail("waitkey yields RES_EOF once pipe is closed") }
+    }
+
+    let fd = unsafe { std::os::pipe().unwrap() };
+    let stream = std::io::pipe::PipeStream::open(fd.reader).unwrap();
+    let mut tk = termkey::TermKey::from_reader(termkey::reader::WithFd::new(stream, fd.reader), termkey::c::TERMKEY_FLAG_NOTERMIOS);
+
+    fd_write(fd.writer, "c");
+    unsafe
+    {
+        libc::close(fd.writer);
+    }
+
+    match tk.waitkey()
+    {
+        termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "c", "waitkey reads c from PipeStream") }
+        _ => { tap.fail("waitkey reads c from PipeStream") }
+    }
+    match tk.waitkey()
+    {
+        termkey::Eof => { tap.pass("waitkey yields RES_EOF once PipeStream is closed") }
+        _ => { tap.fail("waitkey yields RES_EOF once PipeStream is closed") }
+    }
+}
+
+fn fake_env(vars: &[(&str, &str)], name: &str) -> Option<String>
+{
+    vars.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v.to_string())
+}
+
+#[test]
+fn test_50tty()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(10);
+
+    // not the real environment, which other tests share
+    tap.ok(termkey::tty::env_is_utf8(|name| fake_env(&[("LC_ALL", "en_US.UTF-8")], name)), "LC_ALL=en_US.UTF-8 is UTF-8");
+    tap.ok(!termkey::tty::env_is_utf8(|name| fake_env(&[("LC_ALL", ""), ("LC_CTYPE", "ru_RU.KOI8-R"), ("LANG", "en_US.UTF-8")], name)),
+            "LC_CTYPE=ru_RU.KOI8-R is not UTF-8");
+    tap.ok(termkey::tty::env_is_utf8(|name| fake_env(&[("LC_CTYPE", "C.utf8")], name)), "LC_CTYPE=C.utf8 is UTF-8");
+    tap.ok(!termkey::tty::env_is_utf8(|name| fake_env(&[], name)), "no locale is not UTF-8");
+
+    // a terminal as fd is used as it is
+    let harness = pty::Harness::new();
+    match termkey::TermKey::open_terminal(harness.slave, "/nonexistent", termkey::c::TERMKEY_FLAG_UTF8 | termkey::c::TERMKEY_FLAG_NOTERMIOS)
+    {
+        Some(mut tk) =>
+        {
+            tap.ok(tk.get_fd() == harness.slave as int && tk.get_flags().contains(termkey::c::TERMKEY_FLAG_NOTERMIOS),
+                    "open_terminal uses a terminal fd, keeping NOTERMIOS");
+        }
+        None => { tap.fail("open_terminal uses a terminal fd, keeping NOTERMIOS") }
+    }
+    tap.ok(harness.termios().canonical(), "the terminal fd is still open and put back after drop");
+
+    // otherwise the path is opened, as /dev/tty would be
+    let (master, slave) = pty::open();
+    let path = pty::path(master);
+    unsafe
+    {
+        libc::close(slave);
+    }
+    pty::set_nonblock(master);
+    match termkey::TermKey::open_terminal(-1, path.as_slice(), termkey::c::TERMKEY_FLAG_UTF8)
+    {
+        Some(mut tk) =>
+        {
+            let fd = tk.get_fd() as libc::c_int;
+            tap.ok(unsafe { pty::fcntl(fd, pty::F_GETFD) } & pty::FD_CLOEXEC != 0, "open_terminal opens the path close-on-exec");
+
+            fd_write(master, "x");
+            match tk.waitkey()
+            {
+                termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "x", "open_terminal reads from the path") }
+                _ => { tap.fail("open_terminal reads from the path") }
+            }
+        }
+        None => { tap.bypass(2, "open_terminal opens the path") }
+    }
+    // whatever stop wrote, and then nothing has the slave open
+    let mut buf = [0u8, ..256];
+    while unsafe { libc::read(master, buf.as_mut_ptr() as *mut libc::c_void, 256) } > 0 {}
+    tap.is_int(std::os::errno() as libc::c_int, libc::EIO, "open_terminal closes the path on drop");
+
+    tap.ok(termkey::TermKey::open_terminal(-1, "/nonexistent", termkey::c::TERMKEY_FLAG_UTF8).is_none(), "open_terminal with no terminal");
+
+    unsafe
+    {
+        libc::close(master);
+    }
+}
+
+#[test]
+fn test_51telnet()
+{
+    use std::io::{Acceptor, Listener};
+    use std::io::net::tcp::{TcpListener, TcpStream};
+
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(13);
+
+    let listener = TcpListener::bind("127.0.0.1", 0).unwrap();
+    let port = listener.socket_name().unwrap().port;
+    let mut acceptor = listener.listen().unwrap();
+    let mut client = TcpStream::connect("127.0.0.1", port).unwrap();
+    let mut server = acceptor.accept().unwrap();
+
+    let mut tc = termkey::telnet::TelnetClient::new(termkey::c::X_TermKey_Flag::empty());
+    server.write(tc.greeting().as_slice()).unwrap();
+
+    let mut buf = [0u8, ..12];
+    client.read_at_least(12, buf).unwrap();
+    tap.ok(buf.as_slice() == tc.greeting().as_slice(), "client receives greeting");
+
+    // WILL TTYPE, WILL NAWS, NAWS 80x24
+    client.write(&[255, 251, 24, 255, 251, 31, 255, 250, 31, 0, 80, 0, 24, 255, 240]).unwrap();
+
+    let mut buf = [0u8, ..256];
+    let len = server.read_at_least(15, buf).unwrap();
+    let reply = tc.push_bytes(buf.slice_to(len));
+    tap.ok(reply == vec![255, 250, 24, 1, 255, 240], "server asks for TTYPE");
+
+    // TTYPE IS XTERM, then a, CR NUL, Up
+    client.write(&[255, 250, 24, 0, b'X', b'T', b'E', b'R', b'M', 255, 240]).unwrap();
+    client.write("a\r\0\x1b[A".as_bytes()).unwrap();
+    client.close_write().unwrap();
+
+    loop
+    {
+        match server.read(buf)
+        {
+            Ok(len) => { tc.push_bytes(buf.slice_to(len)); }
+            Err(_) => break,
+        }
+    }
+
+    tap.is_str(tc.get_term(), "xterm", "TTYPE sets term");
+
+    for expect in ["Resize(80x24)", "a", "Enter", "Up"].iter()
+    {
+        match tc.getkey()
+        {
+            termkey::Key(key) =>
+            {
+                let s = tc.termkey().strfkey(&key, termkey::c::TermKeyFormat::empty());
+                tap.is_str(s, *expect, format!("telnet key {}", expect).as_slice());
+            }
+            _ => { tap.fail(format!("telnet key {}", expect).as_slice()) }
+        }
+    }
+
+    // a subnegotiation too long to be NAWS or TTYPE
+    let mut decoder = termkey::telnet::TelnetDecoder::new();
+    let mut bytes = vec![255, 250, 24, 0];
+    bytes.grow(100, b'A');
+    bytes.push_all(&[255, 240, 255, 250, 31, 0, 80, 0, 24, 255, 240]);
+    let events = decoder.feed(bytes.as_slice());
+    tap.ok(events.len() == 1 && match events[0] { termkey::telnet::WindowSize(24, 80) => true, _ => false },
+            "oversized subnegotiation is dropped");
+
+    // b, TTYPE IS XTERM, Up
+    let mut tc = termkey::telnet::TelnetClient::new(termkey::c::X_TermKey_Flag::empty());
+    tc.termkey().set_waittime(123);
+    let mut bytes = vec![b'b', 255, 250, 24, 0, b'X', b'T', b'E', b'R', b'M', 255, 240];
+    bytes.push_all("\x1b[A".as_bytes());
+    tc.push_bytes(bytes.as_slice());
+
+    tap.is_str(tc.get_term(), "vt100", "TTYPE waits while libtermkey has input");
+    for expect in ["b", "Up"].iter()
+    {
+        match tc.getkey()
+        {
+            termkey::Key(key) =>
+            {
+                let s = tc.termkey().strfkey(&key, termkey::c::TermKeyFormat::empty());
+                tap.is_str(s, *expect, format!("telnet key {} around TTYPE", expect).as_slice());
+            }
+            _ => { tap.fail(format!("telnet key {} around TTYPE", expect).as_slice()) }
+        }
+    }
+    tap.is_str(tc.get_term(), "xterm", "TTYPE sets term once libtermkey is empty");
+    tap.is_int(tc.termkey().get_waittime(), 123, "waittime is kept for the new term");
+}
+
+#[test]
+fn test_52kbd()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(10);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    let ctrl = termkey::c::TERMKEY_KEYMOD_CTRL;
+    let alt = termkey::c::TERMKEY_KEYMOD_ALT;
+    let none = termkey::c::X_TermKey_KeyMod::empty();
+
+    match termkey::kbd::parse(&mut tk, "C-x C-f")
+    {
+        Some(keys) =>
+        {
+            tap.ok(keys == vec![termkey::keymap::unicode('x', ctrl), termkey::keymap::unicode('f', ctrl)], "parse C-x C-f");
+        }
+        None => { tap.fail("parse C-x C-f") }
+    }
+
+    match termkey::kbd::parse(&mut tk, "M-<up> <f5> C-M-% RET TAB")
+    {
+        Some(keys) =>
+        {
+            tap.ok(keys == vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_UP, mods: alt},
+                    termkey::FunctionEvent{num: 5, mods: none},
+                    termkey::keymap::unicode('%', ctrl | alt),
+                    termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_ENTER, mods: none},
+                    termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_TAB, mods: none}], "parse M-<up> <f5> C-M-% RET TAB");
+        }
+        None => { tap.fail("parse M-<up> <f5> C-M-% RET TAB") }
+    }
+
+    tap.ok(termkey::kbd::parse(&mut tk, "SPC") == Some(vec![termkey::keymap::unicode(' ', none)]), "parse SPC");
+    tap.ok(termkey::kbd::parse(&mut tk, "<prior>") == Some(vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_PAGEUP, mods: none}]), "parse <prior>");
+    tap.ok(termkey::kbd::parse(&mut tk, "s-a").is_none(), "parse s-a fails");
+    tap.ok(termkey::kbd::parse(&mut tk, "<nosuchkey>").is_none(), "parse <nosuchkey> fails");
+
+    // what getkey gives for the same keys
+    tk.push_bytes("\x18\x06\x1b[A".as_bytes());
+    let mut got = Vec::new();
+    loop
+    {
+        match tk.getkey()
+        {
+            termkey::Key(key) => got.push(key),
+            _ => break,
+        }
+    }
+    tap.ok(termkey::kbd::parse(&mut tk, "C-x C-f <up>") == Some(got), "parse matches getkey");
+
+    let keys = termkey::kbd::parse(&mut tk, "C-x C-f M-<up> <f5> C-M-% RET SPC DEL <next>").unwrap();
+    tap.is_str(termkey::kbd::format(&mut tk, keys.as_slice()), "C-x C-f M-<up> <f5> C-M-% RET SPC DEL <next>", "format round-trips");
+
+    let keys = vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_ENTER, mods: ctrl}];
+    tap.is_str(termkey::kbd::format(&mut tk, keys.as_slice()), "C-<return>", "format C-<return>");
+
+    tk.set_flags(termkey::c::TERMKEY_FLAG_SPACESYMBOL);
+    tap.ok(termkey::kbd::parse(&mut tk, "C-SPC") == Some(vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_SPACE, mods: ctrl}]), "parse C-SPC with SPACESYMBOL");
+}
+
+#[test]
+fn test_53vscode()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(12);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::X_TermKey_Flag::empty());
+
+    let ctrl = termkey::c::TERMKEY_KEYMOD_CTRL;
+    let shift = termkey::c::TERMKEY_KEYMOD_SHIFT;
+    let alt = termkey::c::TERMKEY_KEYMOD_ALT;
+
+    tap.ok(termkey::vscode::parse(&mut tk, "ctrl+shift+p") == Some(vec![termkey::keymap::unicode('p', ctrl | shift)]), "parse ctrl+shift+p");
+    tap.ok(termkey::vscode::parse(&mut tk, "Ctrl-Shift-P") == Some(vec![termkey::keymap::unicode('p', ctrl | shift)]), "parse Ctrl-Shift-P");
+    tap.ok(termkey::vscode::parse(&mut tk, "alt+left") == Some(vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_LEFT, mods: alt}]), "parse alt+left");
+    tap.ok(termkey::vscode::parse(&mut tk, "cmd+k cmd+s").is_none(), "parse cmd+k cmd+s fails");
+    tap.ok(termkey::vscode::parse(&mut tk, "win+s").is_none() && termkey::vscode::parse(&mut tk, "super+s").is_none(), "parse win+s and super+s fail");
+    tap.ok(termkey::vscode::parse(&mut tk, "shift+a") == Some(vec![termkey::keymap::unicode('A', termkey::c::X_TermKey_KeyMod::empty())]), "parse shift+a");
+    tap.ok(termkey::vscode::parse(&mut tk, "alt+shift+a") == Some(vec![termkey::keymap::unicode('A', alt)]), "parse alt+shift+a");
+
+    tk.push_bytes("A".as_bytes());
+    match tk.getkey()
+    {
+        termkey::Key(key) => { tap.ok(termkey::vscode::parse(&mut tk, "shift+a") == Some(vec![key]), "shift+a matches getkey") }
+        _ => { tap.fail("shift+a matches getkey") }
+    }
+    tap.ok(termkey::vscode::parse(&mut tk, "ctrl+-") == Some(vec![termkey::keymap::unicode('-', ctrl)]), "parse ctrl+-");
+    tap.ok(termkey::vscode::parse(&mut tk, "hyper+x").is_none(), "parse hyper+x fails");
+
+    let keys = termkey::vscode::parse(&mut tk, "ctrl+shift+f5 alt+pagedown numpad_add space shift+a").unwrap();
+    tap.is_str(termkey::vscode::format(&mut tk, keys.as_slice()), "ctrl+shift+f5 alt+pagedown numpad_add space shift+a", "format round-trips");
+
+    // the same key, whichever notation it was written in
+    tap.ok(termkey::vscode::parse(&mut tk, "alt+pageup") == termkey::kbd::parse(&mut tk, "M-<prior>"), "vscode and kbd agree");
+}
+
+#[test]
+fn test_54inputrc()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(12);
+
+    let ctrl = termkey::c::TERMKEY_KEYMOD_CTRL;
+    let alt = termkey::c::TERMKEY_KEYMOD_ALT;
+    let shift = termkey::c::TERMKEY_KEYMOD_SHIFT;
+    let none = termkey::c::X_TermKey_KeyMod::empty();
+
+    tap.ok(termkey::inputrc::decode_escapes("\\C-x\\e[A\\M-b\\177\\x41") == Some(vec![0x18, 0x1b, b'[', b'A', 0x1b, b'b', 0x7f, 0x41]), "decode_escapes");
+
+    let mut p = termkey::inputrc::InputrcParser::new("xterm", termkey::c::X_TermKey_Flag::empty());
+
+    let bindings = p.parse("# comment\n\
+                            set editing-mode emacs\n\
+                            \"\\C-x\\C-r\": re-read-init-file\n\
+                            \"\\e[A\": history-search-backward\n\
+                            Meta-Rubout: backward-kill-word\n\
+                            $if mode=emacs\n\
+                            \"\\ep\": \"echo \\\"hi\\\"\\n\"\n\
+                            $endif\n");
+
+    tap.is_int(bindings.len(), 4u, "inputrc has 4 bindings");
+    tap.ok(bindings[0].keys == vec![termkey::keymap::unicode('x', ctrl), termkey::keymap::unicode('r', ctrl)], "\\C-x\\C-r keys");
+    tap.ok(bindings[0].action == termkey::inputrc::Function("re-read-init-file".to_string()), "\\C-x\\C-r action");
+    tap.ok(bindings[1].keys == vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_UP, mods: none}], "\\e[A is Up");
+    tap.ok(bindings[2].keys == vec![termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_DEL, mods: alt}], "Meta-Rubout is M-DEL");
+    tap.ok(bindings[3].keys == vec![termkey::keymap::unicode('p', alt)], "\\ep is M-p");
+    tap.ok(bindings[3].action == termkey::inputrc::Macro("echo \"hi\"\n".as_bytes().to_vec()), "macro action");
+
+    let mut tk = termkey::TermKey::new_abstract("xterm", termkey::c::X_TermKey_Flag::empty());
+
+    tap.ok(termkey::tmux::parse(&mut tk, "C-b") == Some(termkey::keymap::unicode('b', ctrl)), "tmux C-b");
+    tap.ok(termkey::tmux::parse(&mut tk, "M-Up") == Some(termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_UP, mods: alt}), "tmux M-Up");
+    tap.ok(termkey::tmux::parse(&mut tk, "S-F5") == Some(termkey::FunctionEvent{num: 5, mods: shift}), "tmux S-F5");
+
+    let key = termkey::tmux::parse(&mut tk, "c-npage").unwrap();
+    tap.ok(termkey::tmux::format(&mut tk, &key) == Some("C-NPage".to_string()), "tmux format C-NPage");
+}
+
+// feed s to the editor a key at a time, until it has a result
+fn edit(tk: &mut termkey::TermKey, ed: &mut termkey::lineedit::LineEditor<std::io::MemWriter>, s: &str) -> termkey::lineedit::EditResult
+{
+    for key in drain_keys(tk, s.as_bytes()).iter()
+    {
+        match ed.handle_key(key).unwrap()
+        {
+            termkey::lineedit::Continue => {}
+            res => return res,
+        }
+    }
+    termkey::lineedit::Continue
+}
+
+struct WordCompleter
+{
+    words: Vec<String>,
+}
+
+impl termkey::lineedit::Completer for WordCompleter
+{
+    fn complete(&mut self, line: &str, pos: uint) -> (uint, Vec<String>)
+    {
+        let start = line.slice_to(pos).rfind(' ').map_or(0, |i| i + 1);
+        let word = line.slice(start, pos);
+        (start, self.words.iter().filter(|w| w.as_slice().starts_with(word)).map(|w| w.clone()).collect())
+    }
+}
+
+// one that gets the start wrong: a byte into the last character
+struct MidCharCompleter;
+
+impl termkey::lineedit::Completer for MidCharCompleter
+{
+    fn complete(&mut self, _line: &str, pos: uint) -> (uint, Vec<String>)
+    {
+        (pos - 1, vec!["x".to_string()])
+    }
+}
+
+// Push bytes (a few at a time, if the buffer is full), and take every
+// key they give, forced once there is nothing more to come.
+fn drain_keys(tk: &mut termkey::TermKey, bytes: &[u8]) -> Vec<termkey::TermKeyEvent>
+{
+    let mut rest = bytes;
+    let mut keys = Vec::new();
+    loop
+    {
+        let len = tk.push_bytes(rest);
+        rest = rest.slice_from(len);
+        match tk.getkey()
+        {
+            termkey::Key(key) => keys.push(key),
+            termkey::Again if len > 0 => {}
+            termkey::Again => match tk.getkey_force()
+            {
+                termkey::Key(key) => keys.push(key),
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    keys
+}
+
+#[test]
+fn test_55lineedit()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(15);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+    let mut ed = termkey::lineedit::LineEditor::new(std::io::MemWriter::new(), "> ");
+    ed.start().unwrap();
+
+    tap.ok(edit(&mut tk, &mut ed, "hello\x01X\r") == termkey::lineedit::Accept("Xhello".to_string()), "C-a moves to the start");
+
+    tap.ok(edit(&mut tk, &mut ed, "one two\x17\x01\x19\r") == termkey::lineedit::Accept("twoone ".to_string()), "C-w kills and C-y yanks");
+
+    tap.ok(edit(&mut tk, &mut ed, "a\x15b\x15\x19\x1by\r") == termkey::lineedit::Accept("a".to_string()), "M-y yanks the kill before");
+
+    ed.add_history("first");
+    ed.add_history("second");
+    tap.ok(edit(&mut tk, &mut ed, "\x1b[A\x1b[A\r") == termkey::lineedit::Accept("first".to_string()), "Up twice recalls the older line");
+
+    tap.ok(edit(&mut tk, &mut ed, "\x12fir\r") == termkey::lineedit::Accept("first".to_string()), "C-r finds a line in the history");
+
+    tap.ok(edit(&mut tk, &mut ed, "x\x12sec\x07\r") == termkey::lineedit::Accept("x".to_string()), "C-g gives up the search");
+
+    tap.ok(edit(&mut tk, &mut ed, "x\x1b[200~a\r\nb\x01\x1b[201~\r") == termkey::lineedit::Accept("xa b".to_string()), "a paste is inserted as text");
+
+    ed.start().unwrap();
+    edit(&mut tk, &mut ed, "日本\x1b[D");
+    tap.ok(ed.writer().get_ref().ends_with("> 日本\x1b[K\x1b[2D".as_bytes()), "the cursor moves over a wide character");
+    edit(&mut tk, &mut ed, "\x03");
+
+    ed.set_completer(box WordCompleter{words: vec!["foobar".to_string(), "foobaz".to_string()]});
+    tap.ok(edit(&mut tk, &mut ed, "x fo\t\r") == termkey::lineedit::Accept("x fooba".to_string()), "Tab completes the common prefix");
+    ed.set_completer(box MidCharCompleter);
+    tap.ok(edit(&mut tk, &mut ed, "日\t\r") == termkey::lineedit::Accept("日".to_string()), "a completion inside a character is ignored");
+
+    ed.set_mode(termkey::lineedit::Vi);
+    edit(&mut tk, &mut ed, "hello world\x1b");
+    tap.ok(edit(&mut tk, &mut ed, "0dwi>\r") == termkey::lineedit::Accept(">world".to_string()), "vi dw deletes a word");
+
+    tap.ok(edit(&mut tk, &mut ed, "\x04") == termkey::lineedit::EndOfInput, "C-d on an empty line");
+
+    // a paste that doesn't end yet is passed on in pieces, not held
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+    tk.set_string_limit(4);
+    tk.push_bytes("\x1b[200~abcdefghij".as_bytes());
+    match tk.getkey()
+    {
+        termkey::Key(termkey::PasteEvent{data}) => { tap.ok(data.as_slice() == "abcde".as_bytes(), "a long paste is passed on before it ends") }
+        _ => { tap.fail("a long paste is passed on before it ends") }
+    }
+    tk.push_bytes("\x1b[201~x".as_bytes());
+    match tk.getkey()
+    {
+        termkey::Key(termkey::PasteEvent{data}) => { tap.ok(data.as_slice() == "fghij".as_bytes(), "and the rest at the end") }
+        _ => { tap.fail("and the rest at the end") }
+    }
+    match tk.getkey()
+    {
+        termkey::Key(key) => { tap.is_str(tk.strfkey(&key, termkey::c::TermKeyFormat::empty()), "x", "keys after a long paste") }
+        _ => { tap.fail("keys after a long paste") }
+    }
+}
+
+#[deriving(PartialEq, Clone, Show)]
+enum EditorAction
+{
+    Delete,
+    Left,
+    WordForward,
+    Save,
+}
+
+// everything the dispatcher makes of s, less the Pendings
+fn dispatch(tk: &mut termkey::TermKey, d: &mut termkey::modal::Dispatcher<EditorAction>, s: &str) -> Vec<termkey::modal::Dispatch<EditorAction>>
+{
+    let mut out = Vec::new();
+    for key in drain_keys(tk, s.as_bytes()).iter()
+    {
+        match d.dispatch(key)
+        {
+            termkey::modal::Pending => {}
+            res => out.push(res),
+        }
+    }
+    out
+}
+
+#[test]
+fn test_56modal()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(10);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+
+    let mut normal = termkey::modal::Keymap::new("normal");
+    normal.set_counts(true);
+    normal.bind(termkey::kbd::parse(&mut tk, "h").unwrap().as_slice(), termkey::modal::Action(Left));
+    normal.bind(termkey::kbd::parse(&mut tk, "w").unwrap().as_slice(), termkey::modal::Action(WordForward));
+    normal.bind(termkey::kbd::parse(&mut tk, "d").unwrap().as_slice(), termkey::modal::Operator(Delete));
+    normal.bind(termkey::kbd::parse(&mut tk, "C-x C-s").unwrap().as_slice(), termkey::modal::Action(Save));
+    normal.bind(termkey::kbd::parse(&mut tk, "i").unwrap().as_slice(), termkey::modal::PushMode("insert".to_string()));
+
+    let mut insert = termkey::modal::Keymap::new("insert");
+    insert.set_insert(true);
+    insert.bind(termkey::kbd::parse(&mut tk, "ESC").unwrap().as_slice(), termkey::modal::PopMode);
+
+    let mut d = termkey::modal::Dispatcher::new(normal);
+    d.add_mode(insert);
+
+    tap.ok(dispatch(&mut tk, &mut d, "3dw") == vec![termkey::modal::Apply{operator: Delete, motion: WordForward, count: 3}], "3dw");
+    tap.ok(dispatch(&mut tk, &mut d, "2d3w") == vec![termkey::modal::Apply{operator: Delete, motion: WordForward, count: 6}], "2d3w counts multiply");
+    tap.ok(dispatch(&mut tk, &mut d, "dd") == vec![termkey::modal::Apply{operator: Delete, motion: Delete, count: 1}], "dd");
+    tap.ok(dispatch(&mut tk, &mut d, "10h") == vec![termkey::modal::Run{action: Left, count: 10}], "10h");
+    tap.ok(dispatch(&mut tk, &mut d, "\x18\x13") == vec![termkey::modal::Run{action: Save, count: 1}], "C-x C-s");
+
+    tap.ok(dispatch(&mut tk, &mut d, "dq") == vec![termkey::modal::Unbound(termkey::kbd::parse(&mut tk, "d q").unwrap())], "unbound motion cancels the operator");
+    tap.ok(!d.operator_pending(), "no operator pending after an unbound motion");
+
+    tap.ok(dispatch(&mut tk, &mut d, "iab") == vec![termkey::modal::ModeChanged("insert".to_string()), termkey::modal::Insert('a'), termkey::modal::Insert('b')], "i enters insert mode, where characters are inserted");
+    tap.ok(dispatch(&mut tk, &mut d, "\x1b") == vec![termkey::modal::ModeChanged("normal".to_string())], "Escape pops back to normal mode");
+    tap.is_str(d.mode(), "normal", "mode() is normal");
+}
+
+#[test]
+fn test_57pty()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(10);
+
+    let harness = pty::Harness::new();
+    let orig = harness.termios();
+    tap.ok(orig.canonical() && orig.echo(), "pty starts canonical with echo");
+
+    let mut tk = termkey::TermKey::new(harness.slave, termkey::c::X_TermKey_Flag::empty());
+    tap.ok(tk.is_started(), "TermKey on the pty is started");
+    tap.ok(!harness.termios().canonical() && !harness.termios().echo(), "started TermKey turns off ICANON and ECHO");
+
+    harness.script(&[(0, "a"), (20, "\x1b["), (10, "B"), (20, "\x1b"), (200, "b")]);
+    tap.ok(harness.keys(&mut tk, 4) == vec!["a".to_string(), "Down".to_string(), "Escape".to_string(), "b".to_string()],
+            "waitkey joins a split sequence, and times out a lone Escape");
+
+    harness.script(&[(10, "\x1bOP"), (10, "\x1b[1;5C")]);
+    tap.ok(harness.keys(&mut tk, 2) == vec!["F1".to_string(), "C-Right".to_string()], "waitkey reads keys written later");
+
+    tk.stop();
+    tap.ok(harness.termios() == orig, "stop restores the termios");
+
+    tk.start();
+    tap.ok(!harness.termios().canonical(), "start turns off ICANON again");
+
+    tk.set_plain_keys(termkey::termios::PlainKeys::default(true));
+    tap.ok(harness.termios() != orig, "set_plain_keys changes the termios");
+
+    drop(tk);
+    tap.ok(harness.termios() == orig, "drop restores the termios");
+
+    let utf8_raw = termkey::c::TERMKEY_FLAG_UTF8 | termkey::c::TERMKEY_FLAG_RAW;
+    let mut tk = termkey::TermKey::new(harness.slave, termkey::c::X_TermKey_Flag::empty());
+    let detected = tk.get_flags() & utf8_raw;
+    drop(tk);
+    let mut tk = termkey::TermKey::new_with_plain_keys(harness.slave, termkey::c::X_TermKey_Flag::empty(),
+            termkey::termios::PlainKeys::default(true));
+    tap.ok(tk.get_flags() & utf8_raw == detected, "new_with_plain_keys keeps the UTF8/RAW flag libtermkey chose");
+}
+
+static KEY_CORPUS: &'static str = include_str!("test_keys.txt");
+
+// (terminal name, TERM, line, bytes, the keys it may decode as)
+fn read_key_corpus() -> Vec<(String, String, uint, Vec<u8>, Vec<String>)>
+{
+    let mut entries = Vec::new();
+    let mut name = String::new();
+    let mut term = String::new();
+    for (i, line) in KEY_CORPUS.lines().enumerate()
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#")
+        {
+            continue;
+        }
+        let words: Vec<&str> = line.words().collect();
+        if line.starts_with("[")
+        {
+            name = words[0].slice_from(1).to_string();
+            term = words[1].trim_right_chars(']').to_string();
+            continue;
+        }
+        let bytes = termkey::inputrc::decode_escapes(words[0]).expect("corpus bytes");
+        let keys = words[1].split('|').map(|k| k.to_string()).collect();
+        entries.push((name.clone(), term.clone(), i + 1, bytes, keys));
+    }
+    entries
+}
+
+#[test]
+fn test_58corpus()
+{
+    let mut tap = taplib::Tap::new();
+    let entries = read_key_corpus();
+    tap.plan_tests(entries.len());
+
+    let format = termkey::c::TermKeyFormat::empty();
+    let mut current = String::new();
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+    for &(ref name, ref term, line, ref bytes, ref keys) in entries.iter()
+    {
+        if *term != current
+        {
+            tk = termkey::TermKey::new_abstract(term.as_slice(), termkey::c::TERMKEY_FLAG_UTF8);
+            current = term.clone();
+        }
+        let got: Vec<String> = drain_keys(&mut tk, bytes.as_slice()).iter().map(|key| tk.strfkey(key, format)).collect();
+        let mut wanted = Vec::new();
+        for k in keys.iter()
+        {
+            match tk.strpkey(k.as_slice(), format)
+            {
+                Some((key, "")) => wanted.push(tk.strfkey(&key, format)),
+                _ => diag!("line {}: can't parse {}", line, k),
+            }
+        }
+        // one key, and nothing left over
+        let ok = got.len() == 1 && wanted.contains(&got[0]);
+        if !ok
+        {
+            diag!("line {}: got {}", line, got);
+        }
+        tap.ok(ok, format!("{} {}", name, keys[0]).as_slice());
+    }
+}
+
+// deterministic pseudo-random input, so failures can be reproduced
+struct XorShift
+{
+    state: u64,
+}
+
+impl XorShift
+{
+    fn new(seed: u64) -> XorShift
+    {
+        XorShift{state: seed | 1}
+    }
+
+    fn next(&mut self) -> u64
+    {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn below(&mut self, n: uint) -> uint
+    {
+        (self.next() % n as u64) as uint
+    }
+
+    // mostly bytes that matter to the decoder and the notations
+    fn bytes(&mut self, max: uint) -> Vec<u8>
+    {
+        static INTERESTING: &'static [u8] = b"\x1b\x00[O;:?<>~-+^\\\"'CMSAcmsFf0123456789\x7f\x9b\x8f\xc3\xe2\xed\xf4\xff";
+        let len = self.below(max + 1);
+        range(0, len).map(|_|
+        {
+            let r = self.next();
+            if r & 3 == 0 { (r >> 8) as u8 } else { INTERESTING[(r >> 8) as uint % INTERESTING.len()] }
+        }).collect()
+    }
+}
+
+#[test]
+fn test_59fuzz()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(7);
+
+    let nasty: &[&[u8]] = &[
+        b"",
+        b"\x00",
+        b"\x00\x00Up\x00junk",
+        b"\x00\x00\x1b[<0;1;1",
+        b"\x00\x00\x1b[M",
+        b"\x00\x00\x1b[M\xff\xff",
+        b"\x00\x00\x1b[1;1;1;1;1;1;1;1;1;1;1;1;1;1;1;1;1;1;1;1R",
+        b"\x00\x00\x1b[?1;2;3;4$y",
+        b"\x00\x00\x1b[99999999999999999999~",
+        b"\x00\x00\xed\xa0\x80\xf4\x90\x80\x80\xc0\x80\xff",
+        b"\x01\x00\xed\xa0\x80\xf4\x90\x80\x80\xc0\x80\xff",
+        b"\x00\x03\x1b[200~abc",
+        b"\x00\x01\x1bP1$r0m",
+        b"\x00\x01\x1b]11;rgb:",
+        b"C-\x00a",
+        b"<f\x00>",
+        b"\"\\",
+        b"\"\\C-",
+        b"\"\\M-\\C-\": x",
+        b"Control-\xc3\xa9: x",
+        b"ctrl+\x00",
+        b"\xed\xa0\x80",
+    ];
+    for &(name, target) in termkey::fuzz::TARGETS.iter()
+    {
+        for input in nasty.iter()
+        {
+            target(*input);
+        }
+        let mut rng = XorShift::new(0x5eed);
+        for _ in range(0u, 500)
+        {
+            let input = rng.bytes(40);
+            target(input.as_slice());
+        }
+        tap.pass(format!("{} survives", name).as_slice());
+    }
+
+    let mut tk = termkey::TermKey::new_abstract("xterm", termkey::c::TERMKEY_FLAG_UTF8);
+    let format = termkey::c::TermKeyFormat::empty();
+    match tk.strpkey("Up\0junk", format)
+    {
+        Some((termkey::KeySymEvent{sym: termkey::c::TERMKEY_SYM_UP, ..}, rest)) =>
+        {
+            tap.is_str(rest, "\0junk", "strpkey stops at a NUL");
+        }
+        _ => tap.fail("strpkey stops at a NUL"),
+    }
+    tap.ok(tk.keyname2sym("Up\0") == termkey::c::TERMKEY_SYM_UNKNOWN, "keyname2sym with a NUL is unknown");
+    let mut sym = termkey::c::TERMKEY_SYM_UNKNOWN;
+    tap.ok(tk.lookup_keyname("\0Up", &mut sym).is_none(), "lookup_keyname with a NUL first");
+    tap.ok(termkey::kbd::parse(&mut tk, "<Up\0>").is_none(), "kbd name with a NUL");
+}
+
+// every combination of the modifiers
+fn all_mods() -> Vec<termkey::c::X_TermKey_KeyMod>
+{
+    let each = [termkey::c::TERMKEY_KEYMOD_SHIFT, termkey::c::TERMKEY_KEYMOD_ALT, termkey::c::TERMKEY_KEYMOD_CTRL];
+    range(0u, 1 << each.len()).map(|bits|
+    {
+        let mut mods = termkey::c::X_TermKey_KeyMod::empty();
+        for (i, &m) in each.iter().enumerate()
+        {
+            if bits & 1 << i != 0
+            {
+                mods.insert(m);
+            }
+        }
+        mods
+    }).collect()
+}
+
+// a codepoint that UTF-8 input could give, other than a control
+fn arbitrary_char(rng: &mut XorShift) -> char
+{
+    loop
+    {
+        let cp = match rng.below(3)
+        {
+            0 => 0x21 + rng.below(0x7f - 0x21),
+            1 => 0xa0 + rng.below(0x800 - 0xa0),
+            _ => 0x800 + rng.below(0x110000 - 0x800),
+        };
+        if cp == 0xfffe || cp == 0xffff
+        {
+            continue;
+        }
+        match std::char::from_u32(cp as u32)
+        {
+            Some(ch) => return ch,
+            None => continue,
+        }
+    }
+}
+
+// Every symbol and function key, all of printable ASCII, a few
+// non-ASCII characters, and some arbitrary ones, each with every set
+// of modifiers; as getkey would give them, so Space depends on tk.
+fn arbitrary_keys(tk: &mut termkey::TermKey, rng: &mut XorShift) -> Vec<termkey::TermKeyEvent>
+{
+    let mut chars: Vec<char> = range(0x21u8, 0x7f).map(|b| b as char).collect();
+    chars.push_all(&['é', 'ß', 'ж', 'λ', '€', '→', '中', '\U0001f600']);
+    for _ in range(0u, 32)
+    {
+        chars.push(arbitrary_char(rng));
+    }
+
+    let mut keys = Vec::new();
+    for &mods in all_mods().iter()
+    {
+        for sym in termkey::keymap::all_syms().into_iter()
+        {
+            if sym != termkey::c::TERMKEY_SYM_SPACE
+            {
+                keys.push(termkey::KeySymEvent{sym: sym, mods: mods});
+            }
+        }
+        keys.push(termkey::keymap::space(tk, mods));
+        for num in range(1i, 64)
+        {
+            keys.push(termkey::FunctionEvent{num: num, mods: mods});
+        }
+        for &ch in chars.iter()
+        {
+            keys.push(termkey::keymap::unicode(ch, mods));
+        }
+    }
+    keys
+}
+
+#[test]
+fn test_60roundtrip()
+{
+    let mut tap = taplib::Tap::new();
+    let formats = termkey::fuzz::all_formats();
+    tap.plan_tests(formats.len() + 2);
+
+    let mut rng = XorShift::new(0x7e57);
+    for &flags in [termkey::c::TERMKEY_FLAG_UTF8, termkey::c::TERMKEY_FLAG_UTF8 | termkey::c::TERMKEY_FLAG_SPACESYMBOL].iter()
+    {
+        let mut tk = termkey::TermKey::new_abstract("xterm", flags);
+        let keys = arbitrary_keys(&mut tk, &mut rng);
+        let mut check = |tk: &mut termkey::TermKey, format: termkey::c::TermKeyFormat| -> uint
+        {
+            let mut bad = 0u;
+            for key in keys.iter()
+            {
+                let s = tk.strfkey(key, format);
+                let ok = match tk.strpkey(s.as_slice(), format)
+                {
+                    Some((ref back, "")) => back == key,
+                    _ => false,
+                };
+                if !ok
+                {
+                    if bad < 5
+                    {
+                        diag!("{} does not read back", s);
+                    }
+                    bad += 1;
+                }
+            }
+            bad
+        };
+
+        if flags.contains(termkey::c::TERMKEY_FLAG_SPACESYMBOL)
+        {
+            tap.is_int(check(&mut tk, termkey::c::TERMKEY_FORMAT_VIM), 0, "VIM, with SPACESYMBOL");
+            tap.is_int(check(&mut tk, termkey::c::TERMKEY_FORMAT_URWID), 0, "URWID, with SPACESYMBOL");
+        }
+        else
+        {
+            for (i, &format) in formats.iter().enumerate()
+            {
+                tap.is_int(check(&mut tk, format), 0, format!("format combination {}", i).as_slice());
+            }
+        }
+    }
+}
+
+// every key bytes give, as strfkey writes them
+fn keys(tk: &mut termkey::TermKey, bytes: &[u8]) -> Vec<String>
+{
+    drain_keys(tk, bytes).iter().map(|key| tk.strfkey(key, termkey::c::TermKeyFormat::empty())).collect()
+}
+
+#[test]
+fn test_61utf8()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(16);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+
+    tap.ok(keys(&mut tk, b"a\xffb") == vec!["a".to_string(), "InvalidUtf8(ff)".to_string(), "b".to_string()],
+            "a stray byte between characters");
+    tap.ok(keys(&mut tk, b"\xe2\x82x") == vec!["InvalidUtf8(e2 82)".to_string(), "x".to_string()],
+            "a character cut short");
+    tap.ok(keys(&mut tk, b"\xed\xa0\x80") == vec!["InvalidUtf8(ed)".to_string(), "InvalidUtf8(a0)".to_string(), "InvalidUtf8(80)".to_string()],
+            "an encoded surrogate");
+    tap.ok(keys(&mut tk, b"\xc0\xaf") == vec!["InvalidUtf8(c0)".to_string(), "InvalidUtf8(af)".to_string()],
+            "an overlong form");
+    tap.ok(keys(&mut tk, b"\xe2\x82\xac\xf0\x9f\x98\x80") == vec!["€".to_string(), "\U0001f600".to_string()],
+            "valid characters pass");
+    tap.ok(keys(&mut tk, b"\x1b\xc3\xa9") == vec!["A-é".to_string()], "Alt and a character");
+
+    tk.push_bytes(b"\xc3");
+    match tk.getkey()
+    {
+        termkey::Again => tap.pass("half a character waits"),
+        _ => tap.fail("half a character waits"),
+    }
+    tk.push_bytes(b"\xa9");
+    match tk.getkey()
+    {
+        termkey::Key(termkey::UnicodeEvent{codepoint, utf8, ..}) =>
+        {
+            tap.is_int(codepoint, 'é', "codepoint of a character split across reads");
+            tap.is_str(utf8.s(), "é", "utf8 of a character split across reads");
+        }
+        _ => tap.bypass(2, "a character split across reads"),
+    }
+    tap.ok(keys(&mut tk, b"\xc3") == vec!["InvalidUtf8(c3)".to_string()], "forced, half a character is invalid");
+
+    // kitty's protocol can name any codepoint
+    match keys(&mut tk, b"\x1b[55296u").as_slice()
+    {
+        [ref key] => tap.ok(key.as_slice().starts_with("InvalidUtf8("), "CSI u with a surrogate"),
+        _ => tap.fail("CSI u with a surrogate"),
+    }
+
+    let mut raw = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_RAW);
+    raw.set_charset(Some(box termkey::charset::Latin1 as Box<termkey::charset::Charset>));
+    raw.push_bytes(b"\xe9");
+    match raw.getkey_force()
+    {
+        termkey::Key(termkey::UnicodeEvent{utf8, ..}) => tap.is_str(utf8.s(), "é", "raw byte has valid utf8"),
+        _ => tap.fail("raw byte has valid utf8"),
+    }
+
+    tap.ok(termkey::Utf8Char::from_bytes("é".as_bytes()).and_then(|u| u.to_char()) == Some('é'), "Utf8Char from_bytes and to_char");
+    tap.ok(termkey::Utf8Char::from_bytes(b"\xff").is_none() && termkey::Utf8Char::from_bytes(b"ab").is_none(), "from_bytes checks");
+    let bad = termkey::Utf8Char{bytes: unsafe { std::mem::transmute([0xedu8, 0xa0, 0x80, 0, 0, 0, 0]) }};
+    tap.ok(bad.to_char().is_none(), "to_char of a surrogate");
+    tap.is_str(bad.s(), "�", "s() of a surrogate");
+}
+
+// leaves everything as bytes
+struct NoCharset;
+
+impl termkey::charset::Charset for NoCharset
+{
+    fn decode(&self, _: u8) -> Option<char>
+    {
+        None
+    }
+}
+
+#[test]
+fn test_62bytes()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(17);
+
+    let mut tk = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_RAW);
+
+    tk.push_bytes(b"\xe9");
+    match tk.getkey()
+    {
+        termkey::Key(termkey::ByteEvent{byte, mods}) =>
+        {
+            tap.is_int(byte, 0xe9, "ByteEvent.byte");
+            tap.ok(mods.is_empty(), "ByteEvent.mods");
+        }
+        _ => tap.bypass(2, "ByteEvent"),
+    }
+    tap.ok(keys(&mut tk, b"a\x82b") == vec!["a".to_string(), "Byte(82)".to_string(), "b".to_string()],
+            "bytes in order with ASCII");
+    tap.ok(keys(&mut tk, b"\x1b\xe9") == vec!["A-Byte(e9)".to_string()], "ESC and a byte is Alt");
+    tap.ok(keys(&mut tk, b"\x9b\xff") == vec!["Byte(9b)".to_string(), "Byte(ff)".to_string()], "C1 and 0xff as bytes");
+    tap.ok(keys(&mut tk, b"\x1bPa\xe9") == vec!["A-P".to_string(), "a".to_string(), "Byte(e9)".to_string()],
+            "an unfinished string, given up on, still has bytes");
+
+    let alt_byte = termkey::ByteEvent{byte: 0xe9, mods: termkey::c::TERMKEY_KEYMOD_ALT};
+    tap.is_str(tk.strfkey(&alt_byte, termkey::c::TERMKEY_FORMAT_VIM), "<M-Byte(e9)>", "strfkey ByteEvent VIM");
+    tap.ok(tk.strpkey("<M-Byte(e9)>", termkey::c::TERMKEY_FORMAT_VIM) == Some((alt_byte.clone(), "")), "strpkey ByteEvent VIM");
+    tap.ok(tk.strpkey("Alt-Byte(e9) and more", termkey::c::TERMKEY_FORMAT_LONGMOD) == Some((alt_byte.clone(), " and more")),
+            "strpkey ByteEvent LONGMOD");
+    let invalid = termkey::InvalidUtf8Event{bytes: vec![0xe2, 0x82]};
+    tap.ok(tk.strpkey("InvalidUtf8(e2 82)", termkey::c::TermKeyFormat::empty()) == Some((invalid, "")), "strpkey InvalidUtf8Event");
+
+    tk.set_charset(Some(box termkey::charset::Latin1 as Box<termkey::charset::Charset>));
+    tap.ok(keys(&mut tk, b"\xe9\x1b\xe9") == vec!["é".to_string(), "A-é".to_string()], "Latin-1");
+    tap.ok(keys(&mut tk, b"\x85") == vec!["Byte(85)".to_string()], "Latin-1 leaves C1 as bytes");
+
+    tk.set_charset(Some(box termkey::charset::Cp437 as Box<termkey::charset::Charset>));
+    tap.ok(keys(&mut tk, b"\x82\xdb\xe1") == vec!["é".to_string(), "█".to_string(), "ß".to_string()], "CP437");
+
+    tk.set_charset(Some(box termkey::charset::Koi8R as Box<termkey::charset::Charset>));
+    tap.ok(keys(&mut tk, b"\xc1\xe1\xa3") == vec!["а".to_string(), "А".to_string(), "ё".to_string()], "KOI8-R");
+
+    tk.set_charset(Some(box NoCharset as Box<termkey::charset::Charset>));
+    tap.ok(keys(&mut tk, b"\xe9") == vec!["Byte(e9)".to_string()], "a charset of our own");
+
+    tk.set_charset(None);
+    tap.ok(keys(&mut tk, b"\xe9") == vec!["Byte(e9)".to_string()], "no charset");
+
+    let mut utf8 = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+    tap.ok(keys(&mut utf8, b"\xc3\xa9") == vec!["é".to_string()], "no ByteEvents in UTF-8 mode");
+}
+
+#[test]
+fn test_63c1()
+{
+    let mut tap = taplib::Tap::new();
+    tap.plan_tests(20);
+
+    let mut raw = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_RAW);
+    tap.ok(keys(&mut raw, b"\x9bA") == vec!["Byte(9b)".to_string(), "A".to_string()], "C1 is a byte by default");
+
+    raw.set_8bit_controls(true);
+    tap.ok(raw.get_8bit_controls(), "get_8bit_controls");
+    tap.ok(keys(&mut raw, b"\x9bA") == vec!["Up".to_string()], "raw 8-bit CSI");
+    tap.ok(keys(&mut raw, b"\x8fP") == vec!["F1".to_string()], "raw 8-bit SS3");
+    tap.ok(keys(&mut raw, b"\x9b1;5A") == vec!["C-Up".to_string()], "raw 8-bit CSI with modifiers");
+    tap.ok(keys(&mut raw, b"\x90>|xterm\x9c") == vec!["Version(xterm)".to_string()], "raw 8-bit DCS and ST");
+    tap.ok(keys(&mut raw, b"\x9b200~hi\x9b201~") == vec!["Paste(2)".to_string()], "raw 8-bit bracketed paste");
+    tap.ok(keys(&mut raw, b"\x1b\x9bA") == vec!["Escape".to_string(), "Up".to_string()], "raw ESC before 8-bit CSI isn't Alt");
+
+    raw.set_high_bit_alt(true);
+    tap.ok(keys(&mut raw, b"\xe1\x9bB") == vec!["A-a".to_string(), "Down".to_string()], "raw high-bit Alt beside C1");
+    raw.set_8bit_controls(false);
+    tap.ok(keys(&mut raw, b"\xc1") == vec!["A-A".to_string()], "raw high-bit Alt");
+    tap.ok(keys(&mut raw, b"\xd0x\xdd") == vec!["A-P".to_string(), "x".to_string(), "A-]".to_string()],
+            "raw high-bit Alt-P and Alt-] start no string");
+    tap.ok(keys(&mut raw, b"\xdbA\xcfP") == vec!["A-[".to_string(), "A".to_string(), "A-O".to_string(), "P".to_string()],
+            "raw high-bit Alt-[ and Alt-O start no sequence");
+
+    let mut utf8 = termkey::TermKey::new_abstract("vt100", termkey::c::TERMKEY_FLAG_UTF8);
+    utf8.set_8bit_controls(true);
+    tap.ok(keys(&mut utf8, b"\x9bA\xc2\x9bB") == vec!["Up".to_string(), "Down".to_string()], "UTF-8 lone and encoded CSI");
+    tap.ok(keys(&mut utf8, b"\xe2\x9c\x9c\xe2\x82\xac\x9bC") == vec!["✜".to_string(), "€".to_string(), "Right".to_string()],
+            "UTF-8 characters with C1 bytes in them");
+    tap.ok(keys(&mut utf8, b"\x90>|xterm(\xe2\x9c\x9c)\x9c") == vec!["Version(xterm(✜))".to_string()],
+            "UTF-8 8-bit ST after a character that ends in 0x9c");
+    tap.ok(keys(&mut utf8, b"\x1b\xc2\x9bA") == vec!["Escape".to_string(), "Up".to_string()],
+            "UTF-8 ESC before an encoded CSI isn't Alt");
+
+    utf8.set_8bit_controls(false);
+    utf8.set_high_bit_alt(true);
+    tap.ok(keys(&mut utf8, b"\xc3\xa9\xe1b") == vec!["é".to_string(), "A-a".to_string(), "b".to_string()],
+            "UTF-8 high-bit Alt beside a character");
+    tap.ok(keys(&mut utf8, b"\xff") == vec!["A-DEL".to_string()], "UTF-8 high-bit Alt on a byte that is never UTF-8");
+    utf8.push_bytes(b"\xe1");
+    match utf8.getkey()
+    {
+        termkey::Again => tap.pass("UTF-8 high-bit Alt waits for what might be a character"),
+        _ => tap.fail("UTF-8 high-bit Alt waits for what might be a character"),
+    }
+    tap.ok(keys(&mut utf8, b"") == vec!["A-a".to_string()], "and forced, is Alt");
+}
@@ -0,0 +1,113 @@
+// tmux key names: "C-b", "M-Up", "S-F5", "^a", "BSpace".
+
+use c;
+use keymap;
+use {TermKey, TermKeyEvent, UnicodeEvent, FunctionEvent, KeySymEvent};
+
+// tmux names for keys that libtermkey calls something else
+static ALIASES: &'static [(&'static str, c::TermKeySym)] = &[
+    ("BSpace", c::TERMKEY_SYM_BACKSPACE),
+    ("IC", c::TERMKEY_SYM_INSERT),
+    ("DC", c::TERMKEY_SYM_DELETE),
+    ("NPage", c::TERMKEY_SYM_PAGEDOWN),
+    ("PPage", c::TERMKEY_SYM_PAGEUP),
+    ("PgDn", c::TERMKEY_SYM_PAGEDOWN),
+    ("PgUp", c::TERMKEY_SYM_PAGEUP),
+    ("KP/", c::TERMKEY_SYM_KPDIV),
+    ("KP*", c::TERMKEY_SYM_KPMULT),
+    ("KP-", c::TERMKEY_SYM_KPMINUS),
+    ("KP+", c::TERMKEY_SYM_KPPLUS),
+    ("KP.", c::TERMKEY_SYM_KPPERIOD),
+];
+
+// One key; tmux names are case-insensitive.
+pub fn parse(tk: &mut TermKey, s: &str) -> Option<TermKeyEvent>
+{
+    let mut mods = c::X_TermKey_KeyMod::empty();
+    let mut rest = s;
+    loop
+    {
+        if rest.len() > 1 && rest.starts_with("^")
+        {
+            mods.insert(c::TERMKEY_KEYMOD_CTRL);
+            rest = rest.slice_from(1);
+            continue;
+        }
+        if rest.len() > 2 && rest.as_bytes()[1] == b'-'
+        {
+            match rest.as_bytes()[0]
+            {
+                b'C' | b'c' => { mods.insert(c::TERMKEY_KEYMOD_CTRL); rest = rest.slice_from(2); continue; }
+                b'M' | b'm' => { mods.insert(c::TERMKEY_KEYMOD_ALT); rest = rest.slice_from(2); continue; }
+                b'S' | b's' => { mods.insert(c::TERMKEY_KEYMOD_SHIFT); rest = rest.slice_from(2); continue; }
+                _ => {}
+            }
+        }
+        break;
+    }
+
+    if rest.char_len() == 1
+    {
+        let ch = rest.char_at(0);
+        // C-B is C-b, as in tmux
+        let ch = if mods.contains(c::TERMKEY_KEYMOD_CTRL) { ch.to_lowercase() } else { ch };
+        return Some(keymap::unicode(ch, mods));
+    }
+
+    let name = keymap::lower(rest);
+    let name = name.as_slice();
+    match keymap::function_number(name)
+    {
+        Some(num) => return Some(FunctionEvent{num: num, mods: mods}),
+        None => {}
+    }
+    for &(alias, sym) in ALIASES.iter()
+    {
+        if name == keymap::lower(alias).as_slice()
+        {
+            return Some(KeySymEvent{sym: sym, mods: mods});
+        }
+    }
+    match name
+    {
+        "btab" => return Some(KeySymEvent{sym: c::TERMKEY_SYM_TAB, mods: mods | c::TERMKEY_KEYMOD_SHIFT}),
+        "space" => return Some(keymap::space(tk, mods)),
+        _ => {}
+    }
+    keymap::sym_by_name(tk, name).map(|sym| KeySymEvent{sym: sym, mods: mods})
+}
+
+// The other way, as tmux list-keys prints them.
+pub fn format(tk: &mut TermKey, key: &TermKeyEvent) -> Option<String>
+{
+    let (mods, name) = match *key
+    {
+        UnicodeEvent{codepoint: ' ', mods, ..} => (mods, "Space".to_string()),
+        UnicodeEvent{codepoint, mods, ..} => (mods, codepoint.to_string()),
+        FunctionEvent{num, mods} => (mods, format!("F{}", num)),
+        KeySymEvent{sym: c::TERMKEY_SYM_TAB, mods} if mods.contains(c::TERMKEY_KEYMOD_SHIFT) =>
+        {
+            (mods - c::TERMKEY_KEYMOD_SHIFT, "BTab".to_string())
+        }
+        KeySymEvent{sym, mods} =>
+        {
+            let mut alias = None;
+            for &(a, s) in ALIASES.iter()
+            {
+                if s == sym
+                {
+                    alias = Some(a.to_string());
+                    break;
+                }
+            }
+            (mods, alias.unwrap_or_else(|| tk.get_keyname(sym).to_string()))
+        }
+        _ => return None,
+    };
+    let mut s = String::new();
+    if mods.contains(c::TERMKEY_KEYMOD_CTRL) { s.push_str("C-"); }
+    if mods.contains(c::TERMKEY_KEYMOD_ALT) { s.push_str("M-"); }
+    if mods.contains(c::TERMKEY_KEYMOD_SHIFT) { s.push_str("S-"); }
+    s.push_str(name.as_slice());
+    Some(s)
+}
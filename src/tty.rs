@@ -0,0 +1,97 @@
+// The controlling terminal, for when stdin might be redirected.
+
+use libc;
+use libc::c_int;
+
+use c;
+use reader::FdReader;
+use TermKey;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+static O_NOCTTY: c_int = 0o400;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+static O_NOCTTY: c_int = 0x8000;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+static O_CLOEXEC: c_int = 0o2000000;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+static O_CLOEXEC: c_int = 0x1000000;
+#[cfg(target_os = "freebsd")]
+static O_CLOEXEC: c_int = 0x100000;
+#[cfg(target_os = "dragonfly")]
+static O_CLOEXEC: c_int = 0x20000;
+
+extern
+{
+    fn isatty(fd: c_int) -> c_int;
+}
+
+// The first of LC_ALL, LC_CTYPE and LANG that is set decides, as it
+// would for setlocale(). libtermkey's own guess reads LANG, then
+// LC_MESSAGES, then LC_ALL: the wrong category, in the wrong order, so
+// e.g. LC_ALL=C.UTF-8 with LANG=C is RAW to it.
+pub fn locale_is_utf8() -> bool
+{
+    env_is_utf8(|var| ::std::os::getenv(var))
+}
+
+// As locale_is_utf8, with getenv in place of the environment.
+pub fn env_is_utf8(getenv: |&str| -> Option<String>) -> bool
+{
+    for var in ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+    {
+        match getenv(*var)
+        {
+            Some(ref value) if !value.is_empty() =>
+            {
+                let value: String = value.as_slice().chars().map(|c| c.to_lowercase()).collect();
+                return value.as_slice().contains("utf-8") || value.as_slice().contains("utf8");
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+impl TermKey
+{
+    // Uses fd 0 if it is a terminal, and otherwise opens /dev/tty, which
+    // is closed again on drop. None if there is no controlling terminal.
+    //
+    // flags are as for new; unless they say RAW or UTF8, the locale
+    // decides (see locale_is_utf8).
+    pub fn new_controlling_terminal(flags: c::X_TermKey_Flag) -> Option<TermKey>
+    {
+        TermKey::open_terminal(0, "/dev/tty", flags)
+    }
+
+    // As new_controlling_terminal, with fd in place of 0 and path in
+    // place of /dev/tty.
+    pub fn open_terminal(fd: c_int, path: &str, flags: c::X_TermKey_Flag) -> Option<TermKey>
+    {
+        let mut flags = flags;
+        if !flags.intersects(c::TERMKEY_FLAG_RAW | c::TERMKEY_FLAG_UTF8)
+        {
+            flags.insert(if locale_is_utf8() { c::TERMKEY_FLAG_UTF8 } else { c::TERMKEY_FLAG_RAW });
+        }
+
+        if unsafe { isatty(fd) } != 0
+        {
+            return Some(TermKey::from_reader(FdReader::borrowed(fd), flags));
+        }
+        // not for any child processes, and never to become the
+        // controlling terminal
+        let fd = path.with_c_str(|path|
+        {
+            unsafe
+            {
+                libc::open(path, libc::O_RDWR | O_NOCTTY | O_CLOEXEC, 0)
+            }
+        });
+        if fd < 0
+        {
+            return None;
+        }
+        Some(TermKey::from_reader(FdReader::new(fd), flags))
+    }
+}
@@ -0,0 +1,153 @@
+// VS Code (JSON keybindings) notation: "ctrl+shift+p", "alt+left",
+// "cmd+k cmd+s". Modifiers are case-insensitive, and may be separated
+// with '-' as well as '+'.
+//
+// A terminal never sends Cmd (or Win/Super), so bindings with cmd,
+// win or super don't parse: they can't be typed here, and taking them
+// for ctrl would fire them on the wrong key. meta and option mean alt,
+// in the terminal sense. shift with a letter is the capital letter, as
+// getkey has it.
+
+use c;
+use keymap;
+use {TermKey, TermKeyEvent, UnicodeEvent, FunctionEvent, KeySymEvent};
+
+// VS Code names for keys that libtermkey calls something else.
+static ALIASES: &'static [(&'static str, c::TermKeySym)] = &[
+    ("numpad0", c::TERMKEY_SYM_KP0),
+    ("numpad1", c::TERMKEY_SYM_KP1),
+    ("numpad2", c::TERMKEY_SYM_KP2),
+    ("numpad3", c::TERMKEY_SYM_KP3),
+    ("numpad4", c::TERMKEY_SYM_KP4),
+    ("numpad5", c::TERMKEY_SYM_KP5),
+    ("numpad6", c::TERMKEY_SYM_KP6),
+    ("numpad7", c::TERMKEY_SYM_KP7),
+    ("numpad8", c::TERMKEY_SYM_KP8),
+    ("numpad9", c::TERMKEY_SYM_KP9),
+    ("numpad_add", c::TERMKEY_SYM_KPPLUS),
+    ("numpad_subtract", c::TERMKEY_SYM_KPMINUS),
+    ("numpad_multiply", c::TERMKEY_SYM_KPMULT),
+    ("numpad_divide", c::TERMKEY_SYM_KPDIV),
+    ("numpad_separator", c::TERMKEY_SYM_KPCOMMA),
+    ("numpad_decimal", c::TERMKEY_SYM_KPPERIOD),
+];
+
+fn modifier(name: &str) -> Option<c::X_TermKey_KeyMod>
+{
+    match keymap::lower(name).as_slice()
+    {
+        "ctrl" | "control" => Some(c::TERMKEY_KEYMOD_CTRL),
+        "alt" | "meta" | "option" | "opt" => Some(c::TERMKEY_KEYMOD_ALT),
+        "shift" => Some(c::TERMKEY_KEYMOD_SHIFT),
+        _ => None,
+    }
+}
+
+// Space-separated chords.
+pub fn parse(tk: &mut TermKey, s: &str) -> Option<Vec<TermKeyEvent>>
+{
+    let mut keys = Vec::new();
+    for chord in s.words()
+    {
+        match parse_chord(tk, chord)
+        {
+            Some(key) => keys.push(key),
+            None => return None,
+        }
+    }
+    if keys.is_empty() { None } else { Some(keys) }
+}
+
+fn parse_chord(tk: &mut TermKey, chord: &str) -> Option<TermKeyEvent>
+{
+    let mut mods = c::X_TermKey_KeyMod::empty();
+    let mut rest = chord;
+    loop
+    {
+        // a separator at the start is the key itself, as in "ctrl+-"
+        let sep = rest.char_indices().skip(1).find(|&(_, ch)| ch == '+' || ch == '-');
+        match sep
+        {
+            Some((i, _)) =>
+            {
+                match modifier(rest.slice_to(i))
+                {
+                    Some(m) => mods.insert(m),
+                    None => return None,
+                }
+                rest = rest.slice_from(i + 1);
+            }
+            None => break,
+        }
+    }
+    if rest.is_empty()
+    {
+        return None;
+    }
+    if rest.char_len() == 1
+    {
+        let ch = rest.char_at(0);
+        return Some(keymap::unicode(ch.to_lowercase(), mods));
+    }
+
+    let name = keymap::lower(rest);
+    match keymap::function_number(name.as_slice())
+    {
+        Some(num) => return Some(FunctionEvent{num: num, mods: mods}),
+        None => {}
+    }
+    for &(alias, sym) in ALIASES.iter()
+    {
+        if name.as_slice() == alias
+        {
+            return Some(KeySymEvent{sym: sym, mods: mods});
+        }
+    }
+    if name.as_slice() == "space"
+    {
+        return Some(keymap::space(tk, mods));
+    }
+    keymap::sym_by_name(tk, name.as_slice()).map(|sym| KeySymEvent{sym: sym, mods: mods})
+}
+
+// The other way, e.g. "ctrl+k ctrl+s". Keys with no name (mouse events,
+// replies) are left out.
+pub fn format(tk: &mut TermKey, keys: &[TermKeyEvent]) -> String
+{
+    let chords: Vec<String> = keys.iter().filter_map(|key| format_key(tk, key)).collect();
+    chords.connect(" ")
+}
+
+pub fn format_key(tk: &mut TermKey, key: &TermKeyEvent) -> Option<String>
+{
+    let (mods, name) = match *key
+    {
+        UnicodeEvent{codepoint: ' ', mods, ..} => (mods, "space".to_string()),
+        UnicodeEvent{codepoint, mods, ..} if codepoint.is_uppercase() =>
+        {
+            (mods | c::TERMKEY_KEYMOD_SHIFT, codepoint.to_lowercase().to_string())
+        }
+        UnicodeEvent{codepoint, mods, ..} => (mods, codepoint.to_string()),
+        FunctionEvent{num, mods} => (mods, format!("f{}", num)),
+        KeySymEvent{sym, mods} =>
+        {
+            let mut alias = None;
+            for &(a, s) in ALIASES.iter()
+            {
+                if s == sym
+                {
+                    alias = Some(a.to_string());
+                    break;
+                }
+            }
+            (mods, alias.unwrap_or_else(|| keymap::lower(tk.get_keyname(sym))))
+        }
+        _ => return None,
+    };
+    let mut s = String::new();
+    if mods.contains(c::TERMKEY_KEYMOD_CTRL) { s.push_str("ctrl+"); }
+    if mods.contains(c::TERMKEY_KEYMOD_SHIFT) { s.push_str("shift+"); }
+    if mods.contains(c::TERMKEY_KEYMOD_ALT) { s.push_str("alt+"); }
+    s.push_str(name.as_slice());
+    Some(s)
+}
@@ -0,0 +1,253 @@
+// XTGETTCAP (DCS + q Pt ST): ask the terminal itself what its keys send,
+// rather than trusting whatever terminfo entry $TERM happens to name.
+//
+// The terminal answers each name with DCS 1 + r name=value ST, or
+// DCS 0 + r name ST if it doesn't know it, both hex-encoded.
+
+use std::collections::Deque;
+use serialize::hex::{FromHex, ToHex};
+
+use c;
+use da;
+use {TermKey, TermKeyEvent, DcsEvent, DeviceAttributesEvent, FunctionEvent, KeySymEvent};
+
+// terminfo names for the keys libtermkey has symbols for
+static SYM_CAPS: &'static [(&'static str, c::TermKeySym)] = &[
+    ("kbs", c::TERMKEY_SYM_BACKSPACE),
+    ("kent", c::TERMKEY_SYM_ENTER),
+    ("kcuu1", c::TERMKEY_SYM_UP),
+    ("kcud1", c::TERMKEY_SYM_DOWN),
+    ("kcub1", c::TERMKEY_SYM_LEFT),
+    ("kcuf1", c::TERMKEY_SYM_RIGHT),
+    ("kbeg", c::TERMKEY_SYM_BEGIN),
+    ("kfnd", c::TERMKEY_SYM_FIND),
+    ("kich1", c::TERMKEY_SYM_INSERT),
+    ("kdch1", c::TERMKEY_SYM_DELETE),
+    ("kslt", c::TERMKEY_SYM_SELECT),
+    ("kpp", c::TERMKEY_SYM_PAGEUP),
+    ("knp", c::TERMKEY_SYM_PAGEDOWN),
+    ("khome", c::TERMKEY_SYM_HOME),
+    ("kend", c::TERMKEY_SYM_END),
+    ("kcan", c::TERMKEY_SYM_CANCEL),
+    ("kclr", c::TERMKEY_SYM_CLEAR),
+    ("kclo", c::TERMKEY_SYM_CLOSE),
+    ("kcmd", c::TERMKEY_SYM_COMMAND),
+    ("kcpy", c::TERMKEY_SYM_COPY),
+    ("kext", c::TERMKEY_SYM_EXIT),
+    ("khlp", c::TERMKEY_SYM_HELP),
+    ("kmrk", c::TERMKEY_SYM_MARK),
+    ("kmsg", c::TERMKEY_SYM_MESSAGE),
+    ("kmov", c::TERMKEY_SYM_MOVE),
+    ("kopn", c::TERMKEY_SYM_OPEN),
+    ("kopt", c::TERMKEY_SYM_OPTIONS),
+    ("kprt", c::TERMKEY_SYM_PRINT),
+    ("krdo", c::TERMKEY_SYM_REDO),
+    ("kref", c::TERMKEY_SYM_REFERENCE),
+    ("krfr", c::TERMKEY_SYM_REFRESH),
+    ("krpl", c::TERMKEY_SYM_REPLACE),
+    ("krst", c::TERMKEY_SYM_RESTART),
+    ("kres", c::TERMKEY_SYM_RESUME),
+    ("ksav", c::TERMKEY_SYM_SAVE),
+    ("kspd", c::TERMKEY_SYM_SUSPEND),
+    ("kund", c::TERMKEY_SYM_UNDO),
+];
+
+// The extended names xterm uses for modified keys: the bare name is
+// shifted, and a suffix 3 to 8 is the usual CSI modifier parameter.
+static MOD_CAPS: &'static [(&'static str, c::TermKeySym)] = &[
+    ("kUP", c::TERMKEY_SYM_UP),
+    ("kDN", c::TERMKEY_SYM_DOWN),
+    ("kLFT", c::TERMKEY_SYM_LEFT),
+    ("kRIT", c::TERMKEY_SYM_RIGHT),
+    ("kHOM", c::TERMKEY_SYM_HOME),
+    ("kEND", c::TERMKEY_SYM_END),
+    ("kIC", c::TERMKEY_SYM_INSERT),
+    ("kDC", c::TERMKEY_SYM_DELETE),
+    ("kPRV", c::TERMKEY_SYM_PAGEUP),
+    ("kNXT", c::TERMKEY_SYM_PAGEDOWN),
+];
+
+// 1 + the modifier bits, as in CSI 1;5A
+fn param_mods(param: uint) -> Option<c::X_TermKey_KeyMod>
+{
+    if param < 2 || param > 8
+    {
+        return None;
+    }
+    let bits = param - 1;
+    let mut mods = c::X_TermKey_KeyMod::empty();
+    if bits & 1 != 0 { mods.insert(c::TERMKEY_KEYMOD_SHIFT); }
+    if bits & 2 != 0 { mods.insert(c::TERMKEY_KEYMOD_ALT); }
+    if bits & 4 != 0 { mods.insert(c::TERMKEY_KEYMOD_CTRL); }
+    Some(mods)
+}
+
+// The key a terminfo key capability stands for, e.g. "kf5" is F5 and
+// "kLFT5" is Ctrl-Left.
+pub fn key_for_capability(name: &str) -> Option<TermKeyEvent>
+{
+    for &(cap, sym) in SYM_CAPS.iter()
+    {
+        if name == cap
+        {
+            return Some(KeySymEvent{sym: sym, mods: c::X_TermKey_KeyMod::empty()});
+        }
+    }
+    if name == "kcbt"
+    {
+        return Some(KeySymEvent{sym: c::TERMKEY_SYM_TAB, mods: c::TERMKEY_KEYMOD_SHIFT});
+    }
+    if name.starts_with("kf")
+    {
+        return match from_str::<int>(name.slice_from(2))
+        {
+            Some(num) if num >= 1 && num <= 63 =>
+            {
+                Some(FunctionEvent{num: num, mods: c::X_TermKey_KeyMod::empty()})
+            }
+            _ => None,
+        };
+    }
+    for &(cap, sym) in MOD_CAPS.iter()
+    {
+        if !name.starts_with(cap)
+        {
+            continue;
+        }
+        let suffix = name.slice_from(cap.len());
+        let mods = if suffix.is_empty()
+        {
+            Some(c::TERMKEY_KEYMOD_SHIFT)
+        }
+        else
+        {
+            from_str::<uint>(suffix).and_then(|param| param_mods(param))
+        };
+        return mods.map(|mods| KeySymEvent{sym: sym, mods: mods});
+    }
+    None
+}
+
+// Every name key_for_capability knows.
+pub fn key_capabilities() -> Vec<String>
+{
+    let mut names: Vec<String> = SYM_CAPS.iter().map(|&(cap, _)| cap.to_string()).collect();
+    names.push("kcbt".to_string());
+    for num in range(1i, 64)
+    {
+        names.push(format!("kf{}", num));
+    }
+    for &(cap, _) in MOD_CAPS.iter()
+    {
+        names.push(cap.to_string());
+        for param in range(3u, 9)
+        {
+            names.push(format!("{}{}", cap, param));
+        }
+    }
+    names
+}
+
+// "name=value" from a +r reply, both hex
+fn decode_reply(payload: &[u8]) -> Option<(String, Vec<u8>)>
+{
+    let s = match ::std::str::from_utf8(payload)
+    {
+        Some(s) => s,
+        None => return None,
+    };
+    let eq = match s.find('=')
+    {
+        Some(eq) => eq,
+        None => return None,
+    };
+    let name = match s.slice_to(eq).from_hex()
+    {
+        Ok(name) => name,
+        Err(_) => return None,
+    };
+    let value = match s.slice_from(eq + 1).from_hex()
+    {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    match String::from_utf8(name)
+    {
+        Ok(name) => Some((name, value)),
+        Err(_) => None,
+    }
+}
+
+impl TermKey
+{
+    // Asks for each capability, and returns the ones the terminal knows.
+    //
+    // A DA1 request goes last, so the wait is over once that's answered,
+    // as in probe_terminal. Other keys read while waiting are queued.
+    pub fn query_capabilities(&mut self, names: &[&str], timeout_ms: int) -> Vec<(String, Vec<u8>)>
+    {
+        let mut request = Vec::new();
+        for name in names.iter()
+        {
+            // one at a time, since xterm stops at the first unknown name
+            request.push_all(format!("\x1bP+q{}\x1b\\", name.as_bytes().to_hex()).as_bytes());
+        }
+        request.push_all("\x1b[c".as_bytes());
+        self.write_request(request.as_slice());
+
+        let mut caps = Vec::new();
+        let deadline = ::deadline(timeout_ms);
+        loop
+        {
+            match self.waitkey_until(deadline)
+            {
+                Some(DcsEvent{ref params, ref intermediates, ref payload})
+                        if intermediates.as_slice() == "+r" =>
+                {
+                    if params.len() == 1 && params[0] == 1
+                    {
+                        match decode_reply(payload.as_slice())
+                        {
+                            Some(cap) => caps.push(cap),
+                            None => {}
+                        }
+                    }
+                }
+                Some(DeviceAttributesEvent{attrs: da::Primary{..}}) => break,
+                Some(key) => { self.queue.push_back(key); }
+                None => break,
+            }
+        }
+        caps
+    }
+
+    // Asks the terminal for all of key_capabilities(), and teaches the
+    // input the sequences it sends. Returns how many keys were learned.
+    //
+    // Only sequences starting with ESC are used; single bytes such as
+    // kbs=^H are better left to libtermkey, which knows about Ctrl.
+    pub fn probe_key_capabilities(&mut self, timeout_ms: int) -> uint
+    {
+        let names = key_capabilities();
+        let refs: Vec<&str> = names.iter().map(|name| name.as_slice()).collect();
+        let caps = self.query_capabilities(refs.as_slice(), timeout_ms);
+        let mut learned = 0;
+        for &(ref name, ref value) in caps.iter()
+        {
+            if value.len() < 2 || value[0] != 0x1b
+            {
+                continue;
+            }
+            match key_for_capability(name.as_slice())
+            {
+                Some(key) =>
+                {
+                    self.add_key_sequence(value.as_slice(), key);
+                    learned += 1;
+                }
+                None => {}
+            }
+        }
+        learned
+    }
+}